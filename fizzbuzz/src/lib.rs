@@ -1,10 +1,6 @@
 /// Iterates through a range mapping numbers using [to_fizzbuzz_string]
 pub fn fizzbuzz_with_match(count: usize) -> Vec<String> {
-    (1..)
-        .into_iter()
-        .map(|i| to_fizzbuzz_string(i as i32))
-        .take(count)
-        .collect()
+    (1..).map(to_fizzbuzz_string).take(count).collect()
 }
 
 fn to_fizzbuzz_string(i: i32) -> String {
@@ -1,15 +1,121 @@
 use std::fmt;
 use std::str::SplitN;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 /// Redisish command\
-/// The protocol has two commands:
+/// The protocol has the following commands:
 ///
-/// * PUBLISH <message>\n
-/// * RETRIEVE\n
+/// * PUBLISH [TO <channel>] [DELAY <ms> | AT <unix-seconds>] [EX <seconds>] [PRIORITY <0-9>] [KEY <key>] <message>\n
+/// * RETRIEVE [WITHMETA] [ORDERED] [DLQ <channel>] [SINCE <id>]\n
+/// * CHANNELS\n
+/// * CREATE <channel>\n
+/// * DELETE <channel>\n
+/// * FETCH <channel>\n
+/// * ACK <id>\n
+/// * NACK <id>\n
+/// * READ <group> <channel> [<count>]\n
+/// * COMMIT <group> <channel> <id>\n
+/// * REPLAY <channel>\n
+/// * SEARCH <channel> [NOCASE | REGEX] <query>\n
+/// * SUBSCRIBE <channel>\n
+/// * INFO\n
+/// * CLIENT SETNAME <name> | LIST | KILL <id>\n
 #[derive(Eq, PartialEq, Debug)]
 pub enum Command {
-    Publish(String),
-    Retrieve,
+    Publish(Publish),
+    Retrieve(Retrieve),
+    Channels,
+    Create(String),
+    Delete(String),
+    Fetch(String),
+    Ack(u64),
+    Nack(u64),
+    Read {
+        group: String,
+        channel: String,
+        count: Option<usize>,
+    },
+    Commit {
+        group: String,
+        channel: String,
+        id: u64,
+    },
+    Replay(String),
+    Search {
+        channel: String,
+        mode: SearchMode,
+        query: String,
+    },
+    /// Pushes the messages published to the channel as `MESSAGE <message>\n` lines
+    Subscribe(String),
+    /// Server statistics
+    Info,
+    /// Names, lists and disconnects the connections
+    Client(Client),
+}
+
+/// Subcommands of the CLIENT command
+#[derive(Eq, PartialEq, Debug)]
+pub enum Client {
+    /// Names the connection, the name is stored with the published messages, `SETNAME <name>`
+    SetName(String),
+    /// One line per connection, `LIST`
+    List,
+    /// Disconnects the connection with the id, `KILL <id>`
+    Kill(u64),
+}
+
+/// How the query of the SEARCH command matches message bodies
+#[derive(Eq, PartialEq, Debug, Default, Clone, Copy)]
+pub enum SearchMode {
+    /// The body contains the query
+    #[default]
+    Substring,
+    /// The body contains the query ignoring the case, `NOCASE`
+    IgnoreCase,
+    /// The body matches the query as a regular expression, `REGEX`
+    Regex,
+}
+
+/// Message and options of the PUBLISH command
+#[derive(Eq, PartialEq, Debug, Default, Clone)]
+pub struct Publish {
+    /// Channel to publish to, the server decides if it is not specified
+    pub channel: Option<String>,
+    /// When the message becomes visible to readers, right away if not specified
+    pub schedule: Option<Schedule>,
+    /// Time to live after which the message is dropped, forever if not specified
+    pub expire: Option<Duration>,
+    /// From 0 to [MAX_PRIORITY], higher priority messages are read first
+    pub priority: u8,
+    /// Idempotency key, a retried PUBLISH with the same key is stored only once
+    pub idempotency_key: Option<String>,
+    pub message: String,
+}
+
+/// Highest priority of a published message, the default priority is 0
+pub const MAX_PRIORITY: u8 = 9;
+
+/// Delayed delivery of a published message
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Schedule {
+    /// Delay after the message was received, `DELAY <milliseconds>`
+    Delay(Duration),
+    /// Point in time, `AT <seconds since the unix epoch>`
+    At(SystemTime),
+}
+
+/// Options of the RETRIEVE command
+#[derive(Eq, PartialEq, Debug, Default)]
+pub struct Retrieve {
+    /// Reply with one message per line including the message metadata
+    pub with_meta: bool,
+    /// Return higher priority messages first and messages of the same priority oldest first
+    pub ordered: bool,
+    /// Retrieve the dead-letter queue of the channel instead of the mailbox
+    pub dead_letters: Option<String>,
+    /// Retrieve only the messages with a greater id, so polling readers get only new messages
+    pub since: Option<u64>,
 }
 
 /// Redisish parsing error
@@ -33,6 +139,24 @@ pub enum Error {
 /// PUBLISH <message>\n
 /// RETRIEVE\n
 ///
+/// Extensions:
+///
+/// PUBLISH [TO <channel>] [DELAY <ms> | AT <unix-seconds>] [EX <seconds>] [PRIORITY <0-9>] [KEY <key>] <message>\n
+/// RETRIEVE [WITHMETA] [ORDERED] [DLQ <channel>]\n
+/// CHANNELS\n
+/// CREATE <channel>\n
+/// DELETE <channel>\n
+/// FETCH <channel>\n
+/// ACK <id>\n
+/// NACK <id>\n
+/// READ <group> <channel> [<count>]\n
+/// COMMIT <group> <channel> <id>\n
+/// REPLAY <channel>\n
+/// SEARCH <channel> [NOCASE | REGEX] <query>\n
+/// SUBSCRIBE <channel>\n
+/// INFO\n
+/// CLIENT SETNAME <name> | LIST | KILL <id>\n
+///
 /// Edge cases:
 /// * Messages cannot contain newlines. => NewlineInMessage
 /// * Data after the first newline is an error. => NewlineInMessage
@@ -40,7 +164,16 @@ pub enum Error {
 /// * Empty messages are allowed. In this case, the message is PUBLISH \n.
 ///
 /// Other cases (not part of the task):
-/// * RETRIEVE does not have the payload, the only valid RETRIEVE message is `RETRIEVE/n`
+/// * RETRIEVE does not have the payload, only known options are allowed after `RETRIEVE`
+/// * PUBLISH options precede the message. `--` ends the options, so a message
+///   starting with an option keyword is sent as `PUBLISH -- TO whom it may concern\n`
+/// * Channel names and idempotency keys are a single non-empty word
+/// * If both DELAY and AT are given, the last one wins
+/// * Priorities are 0 (default) to 9, RETRIEVE ORDERED returns higher priorities first
+/// * Message ids are unsigned integers
+/// * Group names are a single non-empty word
+/// * The SEARCH query is the rest of the line and cannot be empty, `--` ends the options
+/// * Client names are a single non-empty word, client ids are unsigned integers
 pub fn parse(input: &str) -> Result<Command, Error> {
     check_preconditions(input)?;
 
@@ -48,8 +181,21 @@ pub fn parse(input: &str) -> Result<Command, Error> {
 
     let verb = split.next();
     match verb {
-        Some("PUBLISH") => parse_publish(&mut split),
+        Some("PUBLISH") => parse_publish(input, &mut split),
         Some("RETRIEVE") => parse_retrieve(input, &mut split),
+        Some("CHANNELS") => parse_no_payload(input, &mut split, Command::Channels),
+        Some("CREATE") => parse_channel(input, &mut split).map(Command::Create),
+        Some("DELETE") => parse_channel(input, &mut split).map(Command::Delete),
+        Some("FETCH") => parse_channel(input, &mut split).map(Command::Fetch),
+        Some("ACK") => parse_id(input, &mut split).map(Command::Ack),
+        Some("NACK") => parse_id(input, &mut split).map(Command::Nack),
+        Some("READ") => parse_read(input, &mut split),
+        Some("COMMIT") => parse_commit(input, &mut split),
+        Some("REPLAY") => parse_channel(input, &mut split).map(Command::Replay),
+        Some("SEARCH") => parse_search(input, &mut split),
+        Some("SUBSCRIBE") => parse_channel(input, &mut split).map(Command::Subscribe),
+        Some("INFO") => parse_no_payload(input, &mut split, Command::Info),
+        Some("CLIENT") => parse_client(input, &mut split),
         _ => Err(Error::UnknownVerb),
     }
 }
@@ -74,15 +220,217 @@ fn check_preconditions(input: &str) -> Result<(), Error> {
 }
 
 fn parse_retrieve(input: &str, split: &mut SplitN<char>) -> Result<Command, Error> {
+    let malformed = || Error::Malformed(format!("Malformed: {}", input));
+    let mut retrieve = Retrieve::default();
+    if let Some(options) = split.next() {
+        let mut options = options.split(' ');
+        while let Some(option) = options.next() {
+            match option {
+                "WITHMETA" => retrieve.with_meta = true,
+                "ORDERED" => retrieve.ordered = true,
+                "DLQ" => match options.next() {
+                    Some(channel) if !channel.is_empty() => {
+                        retrieve.dead_letters = Some(channel.into())
+                    }
+                    _ => return Err(malformed()),
+                },
+                "SINCE" => {
+                    let id = options.next().and_then(|id| id.parse().ok());
+                    retrieve.since = Some(id.ok_or_else(malformed)?);
+                }
+                _ => return Err(malformed()),
+            }
+        }
+    }
+    Ok(Command::Retrieve(retrieve))
+}
+
+/// Options which can precede the message of a PUBLISH command
+const PUBLISH_OPTIONS: [&str; 7] = ["--", "TO", "DELAY", "AT", "EX", "PRIORITY", "KEY"];
+
+fn parse_publish(input: &str, split: &mut SplitN<char>) -> Result<Command, Error> {
+    let mut publish = Publish::default();
+    let mut rest = split.next().unwrap_or("");
+    loop {
+        let (option, tail) = rest.split_once(' ').unwrap_or((rest, ""));
+        match option {
+            "--" => {
+                rest = tail;
+                break;
+            }
+            "TO" => {
+                let (channel, tail) = next_argument(input, tail)?;
+                publish.channel = Some(channel.into());
+                rest = tail;
+            }
+            "DELAY" => {
+                let (millis, tail) = next_number(input, tail)?;
+                publish.schedule = Some(Schedule::Delay(Duration::from_millis(millis)));
+                rest = tail;
+            }
+            "AT" => {
+                let (seconds, tail) = next_number(input, tail)?;
+                publish.schedule = Some(Schedule::At(after_epoch(input, seconds)?));
+                rest = tail;
+            }
+            "EX" => {
+                let (seconds, tail) = next_number(input, tail)?;
+                // the expiry must be a representable point in time
+                after_epoch(input, seconds)?;
+                publish.expire = Some(Duration::from_secs(seconds));
+                rest = tail;
+            }
+            "PRIORITY" => {
+                let (priority, tail) = next_number(input, tail)?;
+                if priority > MAX_PRIORITY as u64 {
+                    return Err(Error::Malformed(format!("Malformed: {}", input)));
+                }
+                publish.priority = priority as u8;
+                rest = tail;
+            }
+            "KEY" => {
+                let (key, tail) = next_argument(input, tail)?;
+                publish.idempotency_key = Some(key.into());
+                rest = tail;
+            }
+            _ => break,
+        }
+    }
+    publish.message = rest.into();
+    Ok(Command::Publish(publish))
+}
+
+/// Splits off the next word, which must not be empty
+fn next_argument<'a>(input: &str, rest: &'a str) -> Result<(&'a str, &'a str), Error> {
+    match rest.split_once(' ').unwrap_or((rest, "")) {
+        ("", _) => Err(Error::Malformed(format!("Malformed: {}", input))),
+        argument => Ok(argument),
+    }
+}
+
+/// Point in time `seconds` after the unix epoch, malformed if it is not representable
+fn after_epoch(input: &str, seconds: u64) -> Result<SystemTime, Error> {
+    UNIX_EPOCH
+        .checked_add(Duration::from_secs(seconds))
+        .ok_or_else(|| Error::Malformed(format!("Malformed: {}", input)))
+}
+
+/// Splits off the next word, which must be an unsigned integer
+fn next_number<'a>(input: &str, rest: &'a str) -> Result<(u64, &'a str), Error> {
+    let (number, tail) = next_argument(input, rest)?;
+    match number.parse() {
+        Ok(number) => Ok((number, tail)),
+        Err(_) => Err(Error::Malformed(format!("Malformed: {}", input))),
+    }
+}
+
+fn parse_no_payload(
+    input: &str,
+    split: &mut SplitN<char>,
+    command: Command,
+) -> Result<Command, Error> {
     if split.next().is_some() {
         return Err(Error::Malformed(format!("Malformed: {}", input)));
     }
-    Ok(Command::Retrieve)
+    Ok(command)
+}
+
+fn parse_channel(input: &str, split: &mut SplitN<char>) -> Result<String, Error> {
+    match split.next() {
+        Some(channel) if !channel.is_empty() && !channel.contains(' ') => Ok(channel.into()),
+        _ => Err(Error::Malformed(format!("Malformed: {}", input))),
+    }
+}
+
+fn parse_id(input: &str, split: &mut SplitN<char>) -> Result<u64, Error> {
+    split
+        .next()
+        .and_then(|id| id.parse().ok())
+        .ok_or_else(|| Error::Malformed(format!("Malformed: {}", input)))
+}
+
+fn parse_read(input: &str, split: &mut SplitN<char>) -> Result<Command, Error> {
+    let malformed = || Error::Malformed(format!("Malformed: {}", input));
+    let read = |group: &str, channel: &str, count| Command::Read {
+        group: group.into(),
+        channel: channel.into(),
+        count,
+    };
+    match words(split).as_slice() {
+        [group, channel] => Ok(read(group, channel, None)),
+        [group, channel, count] => {
+            let count = count.parse().map_err(|_| malformed())?;
+            Ok(read(group, channel, Some(count)))
+        }
+        _ => Err(malformed()),
+    }
 }
 
-fn parse_publish(split: &mut SplitN<char>) -> Result<Command, Error> {
-    let payload = split.next().unwrap_or("").into();
-    Ok(Command::Publish(payload))
+fn parse_commit(input: &str, split: &mut SplitN<char>) -> Result<Command, Error> {
+    let malformed = || Error::Malformed(format!("Malformed: {}", input));
+    match words(split).as_slice() {
+        [group, channel, id] => Ok(Command::Commit {
+            group: group.to_string(),
+            channel: channel.to_string(),
+            id: id.parse().map_err(|_| malformed())?,
+        }),
+        _ => Err(malformed()),
+    }
+}
+
+/// Options which can precede the query of a SEARCH command
+const SEARCH_OPTIONS: [&str; 3] = ["--", "NOCASE", "REGEX"];
+
+fn parse_search(input: &str, split: &mut SplitN<char>) -> Result<Command, Error> {
+    let (channel, rest) = next_argument(input, split.next().unwrap_or(""))?;
+    let (option, tail) = rest.split_once(' ').unwrap_or((rest, ""));
+    let (mode, query) = match option {
+        "--" => (SearchMode::Substring, tail),
+        "NOCASE" => (SearchMode::IgnoreCase, tail),
+        "REGEX" => (SearchMode::Regex, tail),
+        _ => (SearchMode::Substring, rest),
+    };
+    if query.is_empty() {
+        return Err(Error::Malformed(format!("Malformed: {}", input)));
+    }
+    Ok(Command::Search {
+        channel: channel.into(),
+        mode,
+        query: query.into(),
+    })
+}
+
+fn parse_client(input: &str, split: &mut SplitN<char>) -> Result<Command, Error> {
+    let malformed = || Error::Malformed(format!("Malformed: {}", input));
+    let client = match words(split).as_slice() {
+        ["SETNAME", name] => Client::SetName(name.to_string()),
+        ["LIST"] => Client::List,
+        ["KILL", id] => Client::Kill(id.parse().map_err(|_| malformed())?),
+        _ => return Err(malformed()),
+    };
+    Ok(Command::Client(client))
+}
+
+/// Splits the payload into words, an empty word (double space) is an error
+fn words<'a>(split: &mut SplitN<'a, char>) -> Vec<&'a str> {
+    match split.next() {
+        Some(payload) if payload.split(' ').all(|word| !word.is_empty()) => {
+            payload.split(' ').collect()
+        }
+        _ => vec![],
+    }
+}
+
+impl Error {
+    /// Name of the error variant, e.g. to count errors by kind
+    pub fn name(&self) -> &'static str {
+        match self {
+            Error::MissingNewline => "MissingNewline",
+            Error::NewlineInMessage => "NewlineInMessage",
+            Error::Malformed(_) => "Malformed",
+            Error::UnknownVerb => "UnknownVerb",
+        }
+    }
 }
 
 impl fmt::Display for Error {
@@ -102,12 +450,139 @@ impl fmt::Display for Error {
 }
 
 impl Command {
+    /// The verb which starts the command
+    pub fn verb(&self) -> &'static str {
+        match self {
+            Command::Publish(_) => "PUBLISH",
+            Command::Retrieve(_) => "RETRIEVE",
+            Command::Channels => "CHANNELS",
+            Command::Create(_) => "CREATE",
+            Command::Delete(_) => "DELETE",
+            Command::Fetch(_) => "FETCH",
+            Command::Ack(_) => "ACK",
+            Command::Nack(_) => "NACK",
+            Command::Read { .. } => "READ",
+            Command::Commit { .. } => "COMMIT",
+            Command::Replay(_) => "REPLAY",
+            Command::Search { .. } => "SEARCH",
+            Command::Subscribe(_) => "SUBSCRIBE",
+            Command::Info => "INFO",
+            Command::Client(_) => "CLIENT",
+        }
+    }
+
     pub fn as_string(&self) -> String {
         match self {
-            Command::Publish(payload) => {
-                format!("PUBLISH {}\n", payload)
+            Command::Publish(publish) => publish.as_string(),
+            Command::Retrieve(retrieve) => retrieve.as_string(),
+            Command::Channels => "CHANNELS\n".to_owned(),
+            Command::Create(channel) => format!("CREATE {}\n", channel),
+            Command::Delete(channel) => format!("DELETE {}\n", channel),
+            Command::Fetch(channel) => format!("FETCH {}\n", channel),
+            Command::Ack(id) => format!("ACK {}\n", id),
+            Command::Nack(id) => format!("NACK {}\n", id),
+            Command::Read {
+                group,
+                channel,
+                count: None,
+            } => format!("READ {} {}\n", group, channel),
+            Command::Read {
+                group,
+                channel,
+                count: Some(count),
+            } => format!("READ {} {} {}\n", group, channel, count),
+            Command::Commit { group, channel, id } => {
+                format!("COMMIT {} {} {}\n", group, channel, id)
+            }
+            Command::Replay(channel) => format!("REPLAY {}\n", channel),
+            Command::Search {
+                channel,
+                mode,
+                query,
+            } => {
+                // the query follows the mode verbatim, only a plain query may need `--`
+                let first_word = query.split(' ').next().unwrap_or("");
+                let mode = match mode {
+                    SearchMode::Substring if SEARCH_OPTIONS.contains(&first_word) => "-- ",
+                    SearchMode::Substring => "",
+                    SearchMode::IgnoreCase => "NOCASE ",
+                    SearchMode::Regex => "REGEX ",
+                };
+                format!("SEARCH {} {}{}\n", channel, mode, query)
             }
-            Command::Retrieve => "RETRIEVE\n".to_owned(),
+            Command::Subscribe(channel) => format!("SUBSCRIBE {}\n", channel),
+            Command::Info => "INFO\n".to_owned(),
+            Command::Client(Client::SetName(name)) => format!("CLIENT SETNAME {}\n", name),
+            Command::Client(Client::List) => "CLIENT LIST\n".to_owned(),
+            Command::Client(Client::Kill(id)) => format!("CLIENT KILL {}\n", id),
+        }
+    }
+}
+
+impl Publish {
+    pub fn as_string(&self) -> String {
+        let mut result = "PUBLISH ".to_owned();
+        if let Some(channel) = &self.channel {
+            result.push_str(&format!("TO {} ", channel));
+        }
+        match self.schedule {
+            Some(Schedule::Delay(delay)) => {
+                result.push_str(&format!("DELAY {} ", delay.as_millis()));
+            }
+            Some(Schedule::At(at)) => {
+                let seconds = at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+                result.push_str(&format!("AT {} ", seconds));
+            }
+            None => {}
+        }
+        if let Some(expire) = self.expire {
+            result.push_str(&format!("EX {} ", expire.as_secs()));
+        }
+        if self.priority > 0 {
+            result.push_str(&format!("PRIORITY {} ", self.priority));
+        }
+        if let Some(key) = &self.idempotency_key {
+            result.push_str(&format!("KEY {} ", key));
+        }
+        let first_word = self.message.split(' ').next().unwrap_or("");
+        if PUBLISH_OPTIONS.contains(&first_word) {
+            result.push_str("-- ");
+        }
+        result.push_str(&self.message);
+        result.push('\n');
+        result
+    }
+}
+
+impl Retrieve {
+    pub fn as_string(&self) -> String {
+        let mut result = "RETRIEVE".to_owned();
+        if self.with_meta {
+            result.push_str(" WITHMETA");
+        }
+        if self.ordered {
+            result.push_str(" ORDERED");
+        }
+        if let Some(channel) = &self.dead_letters {
+            result.push_str(&format!(" DLQ {}", channel));
+        }
+        if let Some(id) = self.since {
+            result.push_str(&format!(" SINCE {}", id));
+        }
+        result.push('\n');
+        result
+    }
+}
+
+impl From<&str> for Publish {
+    fn from(message: &str) -> Self {
+        Publish {
+            channel: None,
+            schedule: None,
+            expire: None,
+            priority: 0,
+            idempotency_key: None,
+            message: message.into(),
         }
     }
 }
@@ -164,18 +639,447 @@ mod tests {
     fn test_publish_empty_ok() {
         let line = "PUBLISH \n";
         let result: Result<Command, Error> = parse(line);
-        let expected = Ok(Command::Publish("".to_owned()));
+        let expected = Ok(Command::Publish("".into()));
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_publish_to_channel_ok() {
+        let line = "PUBLISH TO general Test Message\n";
+        let result: Result<Command, Error> = parse(line);
+        let expected = Ok(Command::Publish(Publish {
+            channel: Some("general".into()),
+            message: "Test Message".into(),
+            ..Default::default()
+        }));
         assert_eq!(result, expected);
     }
 
+    #[test]
+    fn test_publish_to_without_channel_errors_with_malformed() {
+        let line = "PUBLISH TO\n";
+        let result: Result<Command, Error> = parse(line);
+        let expected = Err(Error::Malformed("Malformed: PUBLISH TO\n".into()));
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_publish_escaped_option_ok() {
+        let line = "PUBLISH -- TO whom it may concern\n";
+        let result: Result<Command, Error> = parse(line);
+        let expected = Ok(Command::Publish("TO whom it may concern".into()));
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_publish_delayed_ok() {
+        let line = "PUBLISH TO reminders DELAY 500 stand up\n";
+        let result: Result<Command, Error> = parse(line);
+        let expected = Ok(Command::Publish(Publish {
+            channel: Some("reminders".into()),
+            schedule: Some(Schedule::Delay(Duration::from_millis(500))),
+            message: "stand up".into(),
+            ..Default::default()
+        }));
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_publish_at_ok() {
+        let line = "PUBLISH AT 1700000000 stand up\n";
+        let result: Result<Command, Error> = parse(line);
+        let expected = Ok(Command::Publish(Publish {
+            schedule: Some(Schedule::At(
+                UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            )),
+            ..Publish::from("stand up")
+        }));
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_publish_with_ttl_ok() {
+        let line = "PUBLISH EX 60 short lived\n";
+        let result: Result<Command, Error> = parse(line);
+        let expected = Ok(Command::Publish(Publish {
+            expire: Some(Duration::from_secs(60)),
+            ..Publish::from("short lived")
+        }));
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_publish_at_out_of_range_errors_with_malformed() {
+        let line = format!("PUBLISH AT {} x\n", u64::MAX);
+        assert_eq!(
+            parse(&line),
+            Err(Error::Malformed(format!("Malformed: {}", line)))
+        );
+    }
+
+    #[test]
+    fn test_publish_with_out_of_range_ttl_errors_with_malformed() {
+        let line = format!("PUBLISH EX {} boom\n", u64::MAX);
+        assert_eq!(
+            parse(&line),
+            Err(Error::Malformed(format!("Malformed: {}", line)))
+        );
+    }
+
+    #[test]
+    fn test_publish_with_priority_ok() {
+        let line = "PUBLISH TO alerts PRIORITY 9 disk full\n";
+        let result: Result<Command, Error> = parse(line);
+        let expected = Ok(Command::Publish(Publish {
+            channel: Some("alerts".into()),
+            priority: 9,
+            message: "disk full".into(),
+            ..Default::default()
+        }));
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_publish_with_idempotency_key_ok() {
+        let line = "PUBLISH KEY tui-42-7 hello again\n";
+        let result: Result<Command, Error> = parse(line);
+        let expected = Ok(Command::Publish(Publish {
+            idempotency_key: Some("tui-42-7".into()),
+            ..Publish::from("hello again")
+        }));
+        assert_eq!(result, expected);
+        let line = "PUBLISH KEY\n";
+        let expected = Err(Error::Malformed("Malformed: PUBLISH KEY\n".into()));
+        assert_eq!(parse(line), expected);
+    }
+
+    #[test]
+    fn test_publish_delay_without_number_errors_with_malformed() {
+        for line in &[
+            "PUBLISH DELAY soon hi\n",
+            "PUBLISH AT\n",
+            "PUBLISH DELAY -5 hi\n",
+            "PUBLISH EX 1.5 hi\n",
+            "PUBLISH PRIORITY 10 hi\n",
+            "PUBLISH PRIORITY high hi\n",
+        ] {
+            let expected = Err(Error::Malformed(format!("Malformed: {}", line)));
+            assert_eq!(parse(line), expected);
+        }
+    }
+
+    #[test]
+    fn publish_as_string_roundtrip() {
+        let publishes = [
+            Publish::from("Test Message"),
+            Publish::from(""),
+            Publish::from("-- dashes"),
+            Publish {
+                channel: Some("general".into()),
+                message: "TO whom it may concern".into(),
+                ..Default::default()
+            },
+            Publish {
+                channel: Some("general".into()),
+                schedule: Some(Schedule::Delay(Duration::from_millis(1500))),
+                message: "DELAY is a keyword".into(),
+                ..Default::default()
+            },
+            Publish {
+                schedule: Some(Schedule::Delay(Duration::from_millis(10))),
+                expire: Some(Duration::from_secs(60)),
+                ..Publish::from("EX marks the spot")
+            },
+            Publish {
+                priority: MAX_PRIORITY,
+                ..Publish::from("PRIORITY 1")
+            },
+            Publish {
+                channel: Some("general".into()),
+                idempotency_key: Some("7f3a-1".into()),
+                ..Publish::from("KEY")
+            },
+            Publish {
+                schedule: Some(Schedule::At(
+                    UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+                )),
+                ..Publish::from("reminder")
+            },
+        ];
+        for publish in publishes.iter() {
+            let command = Command::Publish(publish.clone());
+            assert_eq!(parse(&command.as_string()), Ok(command));
+        }
+    }
+
+    #[test]
+    fn test_channels_ok() {
+        assert_eq!(parse("CHANNELS\n"), Ok(Command::Channels));
+        assert_eq!(
+            parse("CHANNELS all\n"),
+            Err(Error::Malformed("Malformed: CHANNELS all\n".into()))
+        );
+    }
+
+    #[test]
+    fn test_create_and_delete_ok() {
+        assert_eq!(
+            parse("CREATE general\n"),
+            Ok(Command::Create("general".into()))
+        );
+        assert_eq!(
+            parse("DELETE general\n"),
+            Ok(Command::Delete("general".into()))
+        );
+    }
+
+    #[test]
+    fn test_create_without_single_channel_errors_with_malformed() {
+        for line in &["CREATE\n", "CREATE \n", "CREATE two words\n"] {
+            let expected = Err(Error::Malformed(format!("Malformed: {}", line)));
+            assert_eq!(parse(line), expected);
+        }
+    }
+
+    #[test]
+    fn test_fetch_ack_nack_ok() {
+        assert_eq!(parse("FETCH jobs\n"), Ok(Command::Fetch("jobs".into())));
+        assert_eq!(parse("ACK 42\n"), Ok(Command::Ack(42)));
+        assert_eq!(parse("NACK 42\n"), Ok(Command::Nack(42)));
+    }
+
+    #[test]
+    fn test_ack_without_id_errors_with_malformed() {
+        for line in &["ACK\n", "ACK \n", "ACK forty-two\n", "NACK 1 2\n"] {
+            let expected = Err(Error::Malformed(format!("Malformed: {}", line)));
+            assert_eq!(parse(line), expected);
+        }
+    }
+
+    #[test]
+    fn test_read_ok() {
+        assert_eq!(
+            parse("READ archiver general\n"),
+            Ok(Command::Read {
+                group: "archiver".into(),
+                channel: "general".into(),
+                count: None
+            })
+        );
+        assert_eq!(
+            parse("READ archiver general 10\n"),
+            Ok(Command::Read {
+                group: "archiver".into(),
+                channel: "general".into(),
+                count: Some(10)
+            })
+        );
+    }
+
+    #[test]
+    fn test_commit_ok() {
+        assert_eq!(
+            parse("COMMIT archiver general 42\n"),
+            Ok(Command::Commit {
+                group: "archiver".into(),
+                channel: "general".into(),
+                id: 42
+            })
+        );
+    }
+
+    #[test]
+    fn test_read_and_commit_with_wrong_arguments_errors_with_malformed() {
+        let lines = [
+            "READ\n",
+            "READ archiver\n",
+            "READ archiver  general\n",
+            "READ archiver general ten\n",
+            "COMMIT archiver general\n",
+            "COMMIT archiver general 1 2\n",
+        ];
+        for line in lines.iter() {
+            let expected = Err(Error::Malformed(format!("Malformed: {}", line)));
+            assert_eq!(parse(line), expected);
+        }
+    }
+
     #[test]
     fn test_retrieve_ok() {
         let line = "RETRIEVE\n";
         let result: Result<Command, Error> = parse(line);
-        let expected = Ok(Command::Retrieve);
+        let expected = Ok(Command::Retrieve(Retrieve::default()));
         assert_eq!(result, expected);
     }
 
+    #[test]
+    fn test_retrieve_with_meta_ok() {
+        let line = "RETRIEVE WITHMETA\n";
+        let result: Result<Command, Error> = parse(line);
+        let expected = Ok(Command::Retrieve(Retrieve {
+            with_meta: true,
+            ..Default::default()
+        }));
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_retrieve_ordered_ok() {
+        let line = "RETRIEVE ORDERED\n";
+        let result: Result<Command, Error> = parse(line);
+        let expected = Ok(Command::Retrieve(Retrieve {
+            ordered: true,
+            ..Default::default()
+        }));
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_retrieve_dead_letters_ok() {
+        let line = "RETRIEVE DLQ jobs WITHMETA\n";
+        let result: Result<Command, Error> = parse(line);
+        let expected = Ok(Command::Retrieve(Retrieve {
+            with_meta: true,
+            dead_letters: Some("jobs".into()),
+            ..Default::default()
+        }));
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_retrieve_since_ok() {
+        let line = "RETRIEVE WITHMETA SINCE 7\n";
+        let result: Result<Command, Error> = parse(line);
+        let expected = Ok(Command::Retrieve(Retrieve {
+            with_meta: true,
+            since: Some(7),
+            ..Default::default()
+        }));
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_retrieve_since_without_id_errors_with_malformed() {
+        for line in ["RETRIEVE SINCE\n", "RETRIEVE SINCE seven\n"] {
+            let expected = Err(Error::Malformed(format!("Malformed: {}", line)));
+            assert_eq!(parse(line), expected);
+        }
+    }
+
+    #[test]
+    fn test_retrieve_dead_letters_without_channel_errors_with_malformed() {
+        let line = "RETRIEVE DLQ\n";
+        let result: Result<Command, Error> = parse(line);
+        let expected = Err(Error::Malformed("Malformed: RETRIEVE DLQ\n".into()));
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_replay_ok() {
+        assert_eq!(parse("REPLAY jobs\n"), Ok(Command::Replay("jobs".into())));
+    }
+
+    #[test]
+    fn test_subscribe_ok() {
+        assert_eq!(
+            parse("SUBSCRIBE general\n"),
+            Ok(Command::Subscribe("general".into()))
+        );
+        assert_eq!(
+            parse("SUBSCRIBE\n"),
+            Err(Error::Malformed("Malformed: SUBSCRIBE\n".into()))
+        );
+    }
+
+    #[test]
+    fn test_search_ok() {
+        let search = |mode, query: &str| {
+            Ok(Command::Search {
+                channel: "general".into(),
+                mode,
+                query: query.into(),
+            })
+        };
+        assert_eq!(
+            parse("SEARCH general disk full\n"),
+            search(SearchMode::Substring, "disk full")
+        );
+        assert_eq!(
+            parse("SEARCH general NOCASE Disk\n"),
+            search(SearchMode::IgnoreCase, "Disk")
+        );
+        assert_eq!(
+            parse("SEARCH general REGEX ^disk [0-9]+%$\n"),
+            search(SearchMode::Regex, "^disk [0-9]+%$")
+        );
+        assert_eq!(
+            parse("SEARCH general -- REGEX\n"),
+            search(SearchMode::Substring, "REGEX")
+        );
+    }
+
+    #[test]
+    fn test_search_without_query_errors_with_malformed() {
+        for line in &["SEARCH\n", "SEARCH general\n", "SEARCH general REGEX\n"] {
+            let expected = Err(Error::Malformed(format!("Malformed: {}", line)));
+            assert_eq!(parse(line), expected);
+        }
+    }
+
+    #[test]
+    fn search_as_string_roundtrip() {
+        let queries = [
+            (SearchMode::Substring, "disk full"),
+            (SearchMode::Substring, "NOCASE is a keyword"),
+            (SearchMode::IgnoreCase, "REGEX"),
+            (SearchMode::Regex, "-- a|b"),
+        ];
+        for (mode, query) in queries {
+            let command = Command::Search {
+                channel: "general".into(),
+                mode,
+                query: query.into(),
+            };
+            assert_eq!(parse(&command.as_string()), Ok(command));
+        }
+    }
+
+    #[test]
+    fn test_retrieve_with_unknown_option_errors_with_malformed() {
+        let line = "RETRIEVE WITHMETA WITHFOO\n";
+        let result: Result<Command, Error> = parse(line);
+        let expected = Err(Error::Malformed(
+            "Malformed: RETRIEVE WITHMETA WITHFOO\n".into(),
+        ));
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn retrieve_as_string_roundtrip() {
+        let dead_letters = Some("jobs".to_owned());
+        let retrieves = [
+            Retrieve::default(),
+            Retrieve {
+                with_meta: true,
+                ..Default::default()
+            },
+            Retrieve {
+                dead_letters: dead_letters.clone(),
+                ..Default::default()
+            },
+            Retrieve {
+                with_meta: true,
+                ordered: true,
+                dead_letters,
+                since: Some(7),
+            },
+        ];
+        for retrieve in retrieves {
+            let command = Command::Retrieve(retrieve);
+            assert_eq!(parse(&command.as_string()), Ok(command));
+        }
+    }
+
     #[test]
     fn test_retrieve_with_space_errors_with_malformed() {
         let line = "RETRIEVE \n";
@@ -200,6 +1104,80 @@ mod tests {
         assert_eq!(result, Err(Error::UnknownVerb));
     }
 
+    #[test]
+    fn test_info_ok() {
+        assert_eq!(parse("INFO\n"), Ok(Command::Info));
+        assert_eq!(parse("INFO\n").unwrap().verb(), "INFO");
+        assert_eq!(
+            parse("INFO all\n"),
+            Err(Error::Malformed("Malformed: INFO all\n".into()))
+        );
+    }
+
+    #[test]
+    fn test_client_ok() {
+        assert_eq!(
+            parse("CLIENT SETNAME tui\n"),
+            Ok(Command::Client(Client::SetName("tui".into())))
+        );
+        assert_eq!(parse("CLIENT LIST\n"), Ok(Command::Client(Client::List)));
+        assert_eq!(
+            parse("CLIENT KILL 7\n"),
+            Ok(Command::Client(Client::Kill(7)))
+        );
+        for command in [
+            Command::Client(Client::SetName("tui".into())),
+            Command::Client(Client::List),
+            Command::Client(Client::Kill(7)),
+        ] {
+            assert_eq!(parse(&command.as_string()), Ok(command));
+        }
+    }
+
+    #[test]
+    fn test_client_with_wrong_arguments_errors_with_malformed() {
+        for line in [
+            "CLIENT\n",
+            "CLIENT SETNAME\n",
+            "CLIENT SETNAME two words\n",
+            "CLIENT LIST all\n",
+            "CLIENT KILL me\n",
+            "CLIENT PAUSE\n",
+        ] {
+            assert_eq!(
+                parse(line),
+                Err(Error::Malformed(format!("Malformed: {}", line)))
+            );
+        }
+    }
+
+    #[test]
+    fn commands_start_with_their_verb() {
+        let commands = [
+            Command::Publish("hi".into()),
+            Command::Retrieve(Retrieve::default()),
+            Command::Channels,
+            Command::Fetch("jobs".into()),
+            Command::Read {
+                group: "tui".into(),
+                channel: "general".into(),
+                count: None,
+            },
+            Command::Subscribe("general".into()),
+            Command::Info,
+            Command::Client(Client::List),
+        ];
+        for command in commands {
+            assert!(command.as_string().starts_with(command.verb()));
+        }
+    }
+
+    #[test]
+    fn error_names() {
+        assert_eq!(Error::Malformed("oops".to_owned()).name(), "Malformed");
+        assert_eq!(parse("PUBLISH").unwrap_err().name(), "MissingNewline");
+    }
+
     #[test]
     fn display_error_test() {
         assert_eq!(
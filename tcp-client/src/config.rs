@@ -0,0 +1,23 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+
+/// Redisish TUI client
+#[derive(Parser, Debug)]
+pub struct Config {
+    /// Address of the redisish server, `host:port` or `unix:<path>` of a Unix domain socket
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    pub server: String,
+
+    /// Connect with TLS, trusting the web PKI root certificates unless --ca-cert is set
+    #[arg(long)]
+    pub tls: bool,
+
+    /// PEM file with the CA certificates to trust, implies --tls
+    #[arg(long)]
+    pub ca_cert: Option<PathBuf>,
+
+    /// Name the server certificate must be valid for, defaults to the host of --server, implies --tls
+    #[arg(long)]
+    pub server_name: Option<String>,
+}
@@ -0,0 +1,228 @@
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::Arc;
+
+use rustls::crypto::ring;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+use crate::config::Config;
+
+/// A connection to the server, plaintext or TLS encrypted
+pub trait Stream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Stream for T {}
+
+/// Opens connections to the configured server
+pub struct Connector {
+    server: String,
+    tls: Option<(Arc<ClientConfig>, ServerName<'static>)>,
+}
+
+impl Connector {
+    pub fn new(config: &Config) -> io::Result<Connector> {
+        let tls = if config.tls || config.ca_cert.is_some() || config.server_name.is_some() {
+            if unix_path(&config.server).is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "TLS is not supported on Unix domain sockets",
+                ));
+            }
+            let roots = match &config.ca_cert {
+                Some(ca_cert) => load_roots(ca_cert)?,
+                None => RootCertStore {
+                    roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+                },
+            };
+            let server_name = config
+                .server_name
+                .clone()
+                .unwrap_or_else(|| host(&config.server).to_owned());
+            let server_name = ServerName::try_from(server_name)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+            Some((client_config(roots)?, server_name))
+        } else {
+            None
+        };
+        Ok(Connector {
+            server: config.server.clone(),
+            tls,
+        })
+    }
+
+    /// Connects to the server, the TLS handshake happens with the first write or read
+    pub fn connect(&self) -> io::Result<Box<dyn Stream>> {
+        if let Some(path) = unix_path(&self.server) {
+            return connect_unix(path);
+        }
+        let tcp_stream = TcpStream::connect(&self.server)?;
+        match &self.tls {
+            None => Ok(Box::new(tcp_stream)),
+            Some((config, server_name)) => {
+                let connection = ClientConnection::new(config.clone(), server_name.clone())
+                    .map_err(io::Error::other)?;
+                Ok(Box::new(StreamOwned::new(connection, tcp_stream)))
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn connect_unix(path: &str) -> io::Result<Box<dyn Stream>> {
+    Ok(Box::new(UnixStream::connect(path)?))
+}
+
+#[cfg(not(unix))]
+fn connect_unix(_path: &str) -> io::Result<Box<dyn Stream>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Unix domain sockets are only supported on Unix",
+    ))
+}
+
+fn load_roots(path: &Path) -> io::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_file_iter(path).map_err(|err| invalid_input(path, err))? {
+        let cert = cert.map_err(|err| invalid_input(path, err))?;
+        roots.add(cert).map_err(|err| invalid_input(path, err))?;
+    }
+    Ok(roots)
+}
+
+fn client_config(roots: RootCertStore) -> io::Result<Arc<ClientConfig>> {
+    let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(Arc::new(config))
+}
+
+fn invalid_input(path: &Path, err: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{}: {}", path.display(), err),
+    )
+}
+
+/// Path of a `unix:<path>` server address
+fn unix_path(server: &str) -> Option<&str> {
+    server.strip_prefix("unix:")
+}
+
+/// Host part of `host:port` or `[ipv6]:port`
+fn host(server: &str) -> &str {
+    let host = server.rsplit_once(':').map_or(server, |(host, _)| host);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use rustls::pki_types::PrivateKeyDer;
+    use rustls::{ServerConfig, ServerConnection};
+
+    use super::*;
+
+    /// Accepts one TLS connection with a self-signed `localhost` certificate and echoes one line
+    fn spawn_echo_server(certified: &rcgen::CertifiedKey) -> String {
+        let key = PrivateKeyDer::try_from(certified.key_pair.serialize_der()).unwrap();
+        let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![certified.cert.der().clone()], key)
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (tcp_stream, _) = listener.accept().unwrap();
+            let connection = ServerConnection::new(Arc::new(config)).unwrap();
+            let mut stream = BufReader::new(StreamOwned::new(connection, tcp_stream));
+            let mut line = String::new();
+            if stream.read_line(&mut line).is_ok() {
+                let _ = stream.get_mut().write_all(line.as_bytes());
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn connects_with_tls_and_custom_ca() {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let ca_cert = dir.path().join("ca.pem");
+        fs::write(&ca_cert, certified.cert.pem()).unwrap();
+        let config = Config {
+            server: spawn_echo_server(&certified),
+            tls: false,
+            ca_cert: Some(ca_cert.clone()),
+            server_name: Some("localhost".to_owned()),
+        };
+
+        let mut stream = BufReader::new(Connector::new(&config).unwrap().connect().unwrap());
+        stream.get_mut().write_all(b"PUBLISH hello\n").unwrap();
+        let mut line = String::new();
+        stream.read_line(&mut line).unwrap();
+        assert_eq!(line, "PUBLISH hello\n");
+    }
+
+    #[test]
+    fn rejects_untrusted_certificate() {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        let config = Config {
+            server: spawn_echo_server(&certified),
+            tls: true,
+            ca_cert: None,
+            server_name: Some("localhost".to_owned()),
+        };
+
+        let mut stream = Connector::new(&config).unwrap().connect().unwrap();
+        assert!(stream.write_all(b"PUBLISH hello\n").is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn connects_to_unix_domain_socket() {
+        use std::os::unix::net::UnixListener;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("redisish.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        let config = Config {
+            server: format!("unix:{}", path.display()),
+            tls: false,
+            ca_cert: None,
+            server_name: None,
+        };
+
+        let mut stream = Connector::new(&config).unwrap().connect().unwrap();
+        stream.write_all(b"RETRIEVE\n").unwrap();
+        let mut line = String::new();
+        let (accepted, _) = listener.accept().unwrap();
+        BufReader::new(accepted).read_line(&mut line).unwrap();
+        assert_eq!(line, "RETRIEVE\n");
+
+        let config = Config {
+            tls: true,
+            ..config
+        };
+        assert!(Connector::new(&config).is_err());
+    }
+
+    #[test]
+    fn server_name_defaults_to_host() {
+        assert_eq!(host("localhost:8080"), "localhost");
+        assert_eq!(host("[::1]:8080"), "::1");
+        assert_eq!(host("example.com"), "example.com");
+    }
+}
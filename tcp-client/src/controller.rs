@@ -1,5 +1,4 @@
-use std::io::Write;
-use std::net::TcpStream;
+use std::io::{self, BufRead, BufReader, Write};
 use std::sync::mpsc::Receiver;
 use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
@@ -7,6 +6,9 @@ use std::time::{Duration, Instant};
 
 use crossterm::event::{self, Event as CEvent, KeyCode, KeyEvent};
 
+use redisish::{Command, Publish};
+
+use crate::connection::Connector;
 use crate::Model;
 
 pub enum Event<I> {
@@ -16,8 +18,11 @@ pub enum Event<I> {
 
 /// Handles key events. Blocks until a key event or a tick arrives.
 /// TODO map errors
-/// TODO inject sender
-pub fn controller(key_events: &Receiver<Event<KeyEvent>>, model: Arc<Mutex<Model>>) -> bool {
+pub fn controller(
+    key_events: &Receiver<Event<KeyEvent>>,
+    connector: &Connector,
+    model: Arc<Mutex<Model>>,
+) -> bool {
     let mut esc = false;
     match key_events.recv().unwrap() {
         Event::Input(event) => match event.code {
@@ -25,7 +30,7 @@ pub fn controller(key_events: &Receiver<Event<KeyEvent>>, model: Arc<Mutex<Model
                 esc = true;
             }
             KeyCode::Enter => {
-                on_enter(&model);
+                on_enter(connector, &model);
             }
             KeyCode::Char(input) => {
                 model.lock().unwrap().composed_push(input);
@@ -46,30 +51,45 @@ pub fn controller(key_events: &Receiver<Event<KeyEvent>>, model: Arc<Mutex<Model
     esc
 }
 
-/// Sends the email to the server and clears the input
-fn on_enter(model: &Arc<Mutex<Model>>) {
+/// Sends the email or creates the channel and clears the input.
+/// Failures are shown in the status area.
+fn on_enter(connector: &Connector, model: &Arc<Mutex<Model>>) {
     let mut model = model.lock().unwrap();
     let composed_email_content = model.composed();
     let selected_channel_name = model.selected_channel_name();
-    let mut client = TcpStream::connect("127.0.0.1:8080").unwrap();
-
-    let message = match selected_channel_name.as_str() {
-        "+" => format!(
-            "PUBLISH {}: {}\n",
-            composed_email_content, "Created the channel"
-        ),
-        _ => format!(
-            "PUBLISH {}: {}\n",
-            selected_channel_name, composed_email_content,
-        ),
+
+    let command = match selected_channel_name.as_str() {
+        "+" => Command::Create(composed_email_content.clone()),
+        _ => Command::Publish(Publish {
+            channel: Some(selected_channel_name.clone()),
+            message: composed_email_content.clone(),
+            ..Default::default()
+        }),
     };
 
-    client.write_all(message.as_ref()).unwrap();
+    // Keep the composed email if it was not sent or the server rejected it, so it can be sent again
+    match send(connector, &command) {
+        Ok(reply) if reply.starts_with("OK") => {
+            if selected_channel_name == "+" {
+                model.select_channel(composed_email_content.as_str());
+            }
+            model.composed_clear();
+            model.clear_status();
+        }
+        Ok(reply) => model.set_status(format!("Rejected: {}", reply.trim_end())),
+        Err(err) => model.set_status(format!("Sending failed: {}", err)),
+    }
+}
 
-    if selected_channel_name == "+" {
-        model.select_channel(composed_email_content.as_str());
+/// Sends the command on a new connection and returns the reply line
+fn send(connector: &Connector, command: &Command) -> io::Result<String> {
+    let mut client = BufReader::new(connector.connect()?);
+    client.get_mut().write_all(command.as_string().as_ref())?;
+    let mut reply = String::new();
+    if client.read_line(&mut reply)? == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
     }
-    model.composed_clear();
+    Ok(reply)
 }
 
 /// Emits key events and ticks at least every 200ms
@@ -97,3 +117,34 @@ pub fn key_events() -> Receiver<Event<KeyEvent>> {
     });
     rx
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use crate::config::Config;
+
+    use super::*;
+
+    #[test]
+    fn failed_send_keeps_the_email_and_shows_the_error() {
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let config = Config {
+            server: addr.to_string(),
+            tls: false,
+            ca_cert: None,
+            server_name: None,
+        };
+        let model = Arc::new(Mutex::new(Model::default()));
+        model.lock().unwrap().composed_push('a');
+
+        on_enter(&Connector::new(&config).unwrap(), &model);
+        let model = model.lock().unwrap();
+        assert_eq!(model.composed(), "a");
+        let status = model.status().unwrap();
+        assert!(status.starts_with("Sending failed: "), "{}", status);
+    }
+}
@@ -1,24 +1,29 @@
 use std::io::{BufRead, BufReader, Write};
-use std::net::TcpStream;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use std::{io, thread};
 
+use clap::Parser;
 use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
 use tui::backend::CrosstermBackend;
 use tui::Terminal;
 
+use config::Config;
+use connection::{Connector, Stream};
 use controller::*;
-use redisish::Command;
+use redisish::{Command, Retrieve};
 use view::draw_tui;
 
-use crate::model::Model;
+use crate::model::{Channel, Email, Model};
 
+mod config;
+mod connection;
 mod controller;
 mod model;
 mod view;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let connector = Arc::new(Connector::new(&Config::parse())?);
     enable_raw_mode().expect("can run in raw mode");
     let backend = CrosstermBackend::new(io::stdout());
     let mut terminal = Terminal::new(backend)?;
@@ -26,12 +31,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let model: Arc<Mutex<Model>> = Arc::new(Mutex::new(Model::default()));
 
-    spawn_tcp_thread(model.clone());
+    spawn_tcp_thread(connector.clone(), model.clone());
 
     let key_events = key_events();
     loop {
         draw_tui(&mut terminal, model.clone())?;
-        let esc = controller(&key_events, model.clone());
+        let esc = controller(&key_events, &connector, model.clone());
         if esc {
             disable_raw_mode()?;
             terminal.show_cursor()?;
@@ -42,35 +47,87 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 }
 
 /// Spawns a thread this modifies the model when new emails arrive
-fn spawn_tcp_thread(model: Arc<Mutex<Model>>) {
-    thread::spawn(move || {
-        loop {
-            match TcpStream::connect("127.0.0.1:8080") {
-                Ok(mut client) => {
-                    loop {
-                        // email1;email2;email3\n
-                        let mut str = String::new();
-                        let result = client.write_all(Command::Retrieve.as_string().as_ref());
-                        if result.is_ok() {
-                            BufReader::new(&client).read_line(&mut str).unwrap();
-                            let mut emails: Vec<String> =
-                                str.trim_end().split(';').map(|it| it.to_owned()).collect();
-                            emails.reverse();
-                            model.lock().unwrap().replace_emails(emails);
-                        } else {
-                            break;
-                        }
-                        thread::sleep(Duration::from_millis(100));
-                    }
-                }
-                Err(e) => {
-                    model.lock().unwrap().replace_emails(vec![
-                        format!("Error occurred: {}", e),
-                        "Have you started the tcp-server?".to_string(),
-                    ]);
-                    thread::sleep(Duration::from_millis(500));
+fn spawn_tcp_thread(connector: Arc<Connector>, model: Arc<Mutex<Model>>) {
+    thread::spawn(move || loop {
+        match connector.connect() {
+            Ok(client) => {
+                let mut client = BufReader::new(client);
+                // newest email id of the model, `None` retrieves all emails
+                let mut since = None;
+                while poll(&mut client, &model, &mut since).is_ok() {
+                    thread::sleep(Duration::from_millis(100));
                 }
             }
+            Err(e) => {
+                model.lock().unwrap().set_error(format!(
+                    "Error occurred: {}\nHave you started the tcp-server?",
+                    e
+                ));
+                thread::sleep(Duration::from_millis(500));
+            }
         }
     });
 }
+
+/// Appends the emails published since the newest email of the model.
+/// All emails are retrieved again if the channel counts show that emails were dropped,
+/// e.g. because they expired or were evicted, or that older emails came back, e.g. replayed
+/// dead letters.
+fn poll(
+    client: &mut BufReader<Box<dyn Stream>>,
+    model: &Mutex<Model>,
+    since: &mut Option<u64>,
+) -> io::Result<()> {
+    // count\n
+    // id received_at peer_addr client_name channel email\n
+    let retrieve = Command::Retrieve(Retrieve {
+        with_meta: true,
+        since: *since,
+        ..Retrieve::default()
+    });
+    client.get_mut().write_all(retrieve.as_string().as_ref())?;
+    let emails = read_lines(client, Email::parse)?;
+    // after RETRIEVE, so emails published in between make the counts larger, not smaller
+    // count\n
+    // channel emails\n
+    client
+        .get_mut()
+        .write_all(Command::Channels.as_string().as_ref())?;
+    let channels = read_lines(client, Channel::parse)?;
+
+    let mut model = model.lock().unwrap();
+    let received = !emails.is_empty();
+    match since {
+        Some(_) => model.append_emails(emails),
+        None => model.replace_emails(emails),
+    }
+    model.replace_channels(channels);
+    // more emails on the server without new ones would be a race only if published just now
+    let resync = model.has_dropped_emails() || (!received && model.has_missing_emails());
+    *since = match resync {
+        true => None,
+        false => Some(model.newest_id().unwrap_or(0)),
+    };
+    Ok(())
+}
+
+/// Reads a multi-line reply: the number of lines followed by the lines.
+/// Lines which cannot be parsed are skipped.
+fn read_lines<T>(
+    reader: &mut impl BufRead,
+    parse: impl Fn(&str) -> Option<T>,
+) -> io::Result<Vec<T>> {
+    let mut str = String::new();
+    reader.read_line(&mut str)?;
+    let count: usize = str
+        .trim_end()
+        .parse()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    let mut items = Vec::with_capacity(count);
+    for _ in 0..count {
+        str.clear();
+        reader.read_line(&mut str)?;
+        items.extend(parse(&str));
+    }
+    Ok(items)
+}
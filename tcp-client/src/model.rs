@@ -1,15 +1,66 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
 use itertools::Itertools;
 
 pub struct Model {
     composed_email_content: String,
     selected_channel_name: String,
-    emails: Vec<String>,
+    emails: Vec<Email>,
+    channels: Vec<Channel>,
+    error: Option<String>,
+    /// Outcome of the last sent email or created channel, if it failed
+    status: Option<String>,
+}
+
+/// An email with the metadata assigned by the server
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Email {
+    pub id: u64,
+    pub received_at: SystemTime,
+    /// Client name or address of the sender
+    pub author: String,
+    pub channel: String,
+    pub body: String,
+}
+
+/// A channel as listed by the server
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Channel {
+    pub name: String,
+    pub emails: u64,
+}
+
+impl Email {
+    /// Parses a `RETRIEVE WITHMETA` line:
+    /// `<id> <received_at> <peer_addr> <client_name> <channel> <body>`
+    pub fn parse(line: &str) -> Option<Email> {
+        let mut split = line.trim_end_matches('\n').splitn(6, ' ');
+        let id = split.next()?.parse().ok()?;
+        let received_at = UNIX_EPOCH + Duration::from_millis(split.next()?.parse().ok()?);
+        let peer_addr = split.next()?;
+        let author = match split.next()? {
+            "-" => peer_addr,
+            client_name => client_name,
+        };
+        Some(Email {
+            id,
+            received_at,
+            author: author.to_owned(),
+            channel: split.next()?.to_owned(),
+            body: split.next()?.to_owned(),
+        })
+    }
 }
 
-fn email_channel(email: &str) -> String {
-    match email.find(':') {
-        Some(i) => email[0..i].to_owned(),
-        _ => "misc".to_owned(),
+impl Channel {
+    /// Parses a `CHANNELS` line: `<name> <emails>`
+    pub fn parse(line: &str) -> Option<Channel> {
+        let (name, emails) = line.trim_end_matches('\n').split_once(' ')?;
+        Some(Channel {
+            name: name.to_owned(),
+            emails: emails.parse().ok()?,
+        })
     }
 }
 
@@ -19,11 +70,84 @@ impl Model {
             composed_email_content: "".to_owned(),
             selected_channel_name: "+".to_string(),
             emails: vec![],
+            channels: vec![],
+            error: None,
+            status: None,
         }
     }
 
-    pub fn replace_emails(&mut self, emails: Vec<String>) {
+    /// Replaces the emails with the ones retrieved from the server, oldest first
+    pub fn replace_emails(&mut self, mut emails: Vec<Email>) {
+        emails.sort_by_key(|email| email.id);
         self.emails = emails;
+        self.error = None;
+    }
+
+    /// Appends emails which are newer than the ones of the model
+    pub fn append_emails(&mut self, mut emails: Vec<Email>) {
+        emails.sort_by_key(|email| email.id);
+        self.emails.extend(emails);
+        self.error = None;
+    }
+
+    pub fn newest_id(&self) -> Option<u64> {
+        self.emails.last().map(|email| email.id)
+    }
+
+    pub fn replace_channels(&mut self, channels: Vec<Channel>) {
+        self.channels = channels;
+    }
+
+    /// Whether a channel has more emails than the server stores, also if it was deleted
+    pub fn has_dropped_emails(&self) -> bool {
+        self.emails_by_channel()
+            .iter()
+            .any(|(channel, emails)| *emails > self.stored(channel))
+    }
+
+    /// Whether the server stores more emails of a channel than the model has
+    pub fn has_missing_emails(&self) -> bool {
+        let emails = self.emails_by_channel();
+        self.channels
+            .iter()
+            .any(|channel| channel.emails > emails.get(channel.name.as_str()).copied().unwrap_or(0))
+    }
+
+    fn emails_by_channel(&self) -> HashMap<&str, u64> {
+        let mut emails = HashMap::new();
+        for email in &self.emails {
+            *emails.entry(email.channel.as_str()).or_default() += 1;
+        }
+        emails
+    }
+
+    /// Number of emails of the channel according to the server
+    fn stored(&self, channel: &str) -> u64 {
+        self.channels
+            .iter()
+            .find(|it| it.name == channel)
+            .map_or(0, |it| it.emails)
+    }
+
+    /// Connection problem to show instead of the emails
+    pub fn set_error(&mut self, error: String) {
+        self.error = Some(error);
+    }
+
+    pub fn error(&self) -> Option<String> {
+        self.error.clone()
+    }
+
+    pub fn set_status(&mut self, status: String) {
+        self.status = Some(status);
+    }
+
+    pub fn clear_status(&mut self) {
+        self.status = None;
+    }
+
+    pub fn status(&self) -> Option<String> {
+        self.status.clone()
     }
 
     pub fn composed(&self) -> String {
@@ -62,29 +186,38 @@ impl Model {
         self.selected_channel_name.clone()
     }
 
-    pub fn emails_for_selected_channel(&self) -> Vec<String> {
-        return self
-            .emails
+    pub fn emails_for_selected_channel(&self) -> Vec<Email> {
+        self.emails
             .iter()
-            .filter(|email| email_channel(email) == self.selected_channel_name.as_str())
-            .map(|s| s.to_owned())
-            .collect();
+            .filter(|email| email.channel == self.selected_channel_name)
+            .cloned()
+            .collect()
     }
 
+    /// Channel names followed by `+` which stands for a new channel
     pub fn channels(&self) -> Vec<String> {
         let mut channels: Vec<String> = self
-            .emails
-            .clone()
-            .into_iter()
-            .map(|email| email_channel(email.as_str()))
-            .unique()
-            .sorted();
+            .channels
+            .iter()
+            .map(|channel| channel.name.clone())
+            .collect();
         channels.push("+".to_owned());
         channels
     }
 
+    /// Same as [Model::channels], including the number of emails
+    pub fn channel_labels(&self) -> Vec<String> {
+        let mut labels: Vec<String> = self
+            .channels
+            .iter()
+            .map(|channel| format!("{} ({})", channel.name, channel.emails))
+            .collect();
+        labels.push("+".to_owned());
+        labels
+    }
+
     pub fn dec_channel(&mut self) {
-        let new_idx = self.selected_channel_idx().saturating_sub(1).max(0);
+        let new_idx = self.selected_channel_idx().saturating_sub(1);
         self.selected_channel_name = self.channels()[new_idx].to_owned();
     }
 
@@ -98,3 +231,84 @@ impl Model {
         self.selected_channel_name = self.channels()[new_idx].to_owned();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_email_with_meta() {
+        let email = Email::parse("7 1500 127.0.0.1:4000 tui general hello world\n").unwrap();
+        assert_eq!(
+            email,
+            Email {
+                id: 7,
+                received_at: UNIX_EPOCH + Duration::from_millis(1500),
+                author: "tui".to_owned(),
+                channel: "general".to_owned(),
+                body: "hello world".to_owned(),
+            }
+        );
+    }
+
+    #[test]
+    fn parse_email_without_client_name_uses_peer_addr() {
+        let email = Email::parse("7 1500 127.0.0.1:4000 - misc hello\n").unwrap();
+        assert_eq!(email.author, "127.0.0.1:4000");
+        assert_eq!(email.body, "hello");
+    }
+
+    #[test]
+    fn parse_malformed_email() {
+        assert_eq!(Email::parse("hello;world;\n"), None);
+    }
+
+    #[test]
+    fn replace_emails_sorts_oldest_first_and_drops_missing_emails() {
+        let email = |id| Email::parse(&format!("{} 1500 127.0.0.1:4000 - misc {}\n", id, id));
+        let mut model = Model::default();
+        model.replace_emails(vec![email(2).unwrap(), email(1).unwrap()]);
+        model.replace_emails(vec![email(3).unwrap(), email(2).unwrap()]);
+        model.select_channel("misc");
+        let ids: Vec<u64> = model
+            .emails_for_selected_channel()
+            .iter()
+            .map(|email| email.id)
+            .collect();
+        assert_eq!(ids, vec![2, 3]);
+    }
+
+    #[test]
+    fn counts_of_the_server_reveal_dropped_and_missing_emails() {
+        let email = |id| Email::parse(&format!("{} 1500 127.0.0.1:4000 - misc {}\n", id, id));
+        let channel = |emails| Channel {
+            name: "misc".to_owned(),
+            emails,
+        };
+        let mut model = Model::default();
+        model.replace_emails(vec![email(1).unwrap()]);
+        model.append_emails(vec![email(3).unwrap(), email(2).unwrap()]);
+        assert_eq!(model.newest_id(), Some(3));
+
+        model.replace_channels(vec![channel(3)]);
+        assert!(!model.has_dropped_emails() && !model.has_missing_emails());
+        model.replace_channels(vec![channel(2)]);
+        assert!(model.has_dropped_emails());
+        model.replace_channels(vec![]);
+        assert!(model.has_dropped_emails());
+        model.replace_channels(vec![channel(4)]);
+        assert!(model.has_missing_emails());
+    }
+
+    #[test]
+    fn parse_channel() {
+        assert_eq!(
+            Channel::parse("general 3\n"),
+            Some(Channel {
+                name: "general".to_owned(),
+                emails: 3
+            })
+        );
+        assert_eq!(Channel::parse("general\n"), None);
+    }
+}
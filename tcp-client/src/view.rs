@@ -1,7 +1,9 @@
+use crate::model::Email;
 use crate::Model;
 use std::io;
 use std::io::Stdout;
 use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
 use tui::{
     backend::CrosstermBackend,
     layout::{Alignment, Constraint, Direction, Layout},
@@ -44,7 +46,7 @@ pub fn draw_tui(
         let mut list_state = ListState::default();
         list_state.select(Some(selected_channel_idx));
         rect.render_stateful_widget(
-            render_channels(model.channels()),
+            render_channels(model.channel_labels()),
             horizontal_layout[0],
             &mut list_state,
         );
@@ -54,19 +56,49 @@ pub fn draw_tui(
             vertical_layout[0],
         );
         rect.render_widget(
-            render_emails(model.emails_for_selected_channel()),
+            render_emails(model.emails_for_selected_channel(), model.error()),
             horizontal_layout[1],
         );
+        rect.render_widget(render_status(model.status()), vertical_layout[2]);
     })?;
     Ok(())
 }
 
-/// Renders emails as a flat list in a box
-fn render_emails<'a>(emails: Vec<String>) -> Paragraph<'a> {
-    let email_spans: Vec<Spans> = emails
-        .iter()
-        .map(|mail| Spans::from(vec![Span::raw(mail.to_owned())]))
-        .collect();
+/// Renders emails as a flat list in a box, prefixed with time (UTC) and author.
+/// Shows the error instead if there is one.
+fn render_emails<'a>(emails: Vec<Email>, error: Option<String>) -> Paragraph<'a> {
+    let email_spans: Vec<Spans> = match error {
+        Some(error) => error
+            .lines()
+            .map(|line| Spans::from(vec![Span::raw(line.to_owned())]))
+            .collect(),
+        None => emails
+            .into_iter()
+            .map(|mail| {
+                let secs = mail
+                    .received_at
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                Spans::from(vec![
+                    Span::styled(
+                        format!(
+                            "{:02}:{:02}:{:02} ",
+                            secs / 3600 % 24,
+                            secs / 60 % 60,
+                            secs % 60
+                        ),
+                        Style::default().fg(Color::DarkGray),
+                    ),
+                    Span::styled(
+                        format!("{} ", mail.author),
+                        Style::default().fg(Color::Cyan),
+                    ),
+                    Span::raw(mail.body),
+                ])
+            })
+            .collect(),
+    };
 
     Paragraph::new(email_spans)
         .alignment(Alignment::Left)
@@ -97,6 +129,22 @@ fn render_compose<'a>(compose: &'a str, channel: &'a str) -> Paragraph<'a> {
         )
 }
 
+/// Renders a box with the failure of the last sent email, if there is one
+fn render_status<'a>(status: Option<String>) -> Paragraph<'a> {
+    Paragraph::new(vec![Spans::from(vec![Span::styled(
+        status.unwrap_or_default(),
+        Style::default().fg(Color::Red),
+    )])])
+    .alignment(Alignment::Left)
+    .block(
+        Block::default()
+            .borders(Borders::ALL)
+            .style(Style::default().fg(Color::White))
+            .title("Status")
+            .border_type(BorderType::Plain),
+    )
+}
+
 /// Renders available channels as a list
 /// Selected element is highlighted
 fn render_channels<'a>(channels: Vec<String>) -> List<'a> {
@@ -0,0 +1,148 @@
+//! Throughput of the mailbox by mailbox size and number of concurrent clients,
+//! run with `cargo bench -p tcp-server`
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use redisish::Publish;
+use tcp_server::mailbox::{EvictionPolicy, PeerAddr, Sender, VecDequeMailbox};
+use tokio::runtime::Runtime;
+
+const SIZES: [usize; 3] = [100, 1_000, 10_000];
+const CLIENTS: [usize; 4] = [1, 2, 4, 8];
+/// Commands executed by each client per iteration
+const COMMANDS: u64 = 1_000;
+
+fn sender() -> Sender {
+    Sender {
+        peer_addr: PeerAddr::Tcp("127.0.0.1:4000".parse().unwrap()),
+        client_name: None,
+    }
+}
+
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(*CLIENTS.iter().max().unwrap())
+        .build()
+        .unwrap()
+}
+
+/// A mailbox filled with `size` emails of 64 bytes each
+fn mailbox(runtime: &Runtime, size: usize) -> Arc<VecDequeMailbox> {
+    let mailbox = Arc::new(VecDequeMailbox::new(size, EvictionPolicy::DropOldest));
+    let publish: Publish = "x".repeat(64).as_str().into();
+    runtime.block_on(async {
+        for _ in 0..size {
+            mailbox.append(&sender(), &publish).await.unwrap();
+        }
+    });
+    mailbox
+}
+
+/// Runs `clients` tasks concurrently, each calling `command` [COMMANDS] times with its index
+fn concurrently<F, Fut>(
+    runtime: &Runtime,
+    iterations: u64,
+    clients: usize,
+    mailbox: &Arc<VecDequeMailbox>,
+    command: F,
+) -> Duration
+where
+    F: Fn(Arc<VecDequeMailbox>, u64) -> Fut + Copy + Send + 'static,
+    Fut: std::future::Future<Output = ()> + Send,
+{
+    runtime.block_on(async {
+        let start = Instant::now();
+        for _ in 0..iterations {
+            let tasks: Vec<_> = (0..clients)
+                .map(|_| {
+                    let mailbox = mailbox.clone();
+                    tokio::spawn(async move {
+                        for i in 0..COMMANDS {
+                            command(mailbox.clone(), i).await;
+                        }
+                    })
+                })
+                .collect();
+            for task in tasks {
+                task.await.unwrap();
+            }
+        }
+        start.elapsed()
+    })
+}
+
+/// A single RETRIEVE by mailbox size
+fn retrieve(c: &mut Criterion) {
+    let runtime = runtime();
+    let mut group = c.benchmark_group("retrieve");
+    for size in SIZES {
+        let mailbox = mailbox(&runtime, size);
+        // 64 bytes and a separator per email
+        group.throughput(Throughput::Bytes(size as u64 * 65));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &mailbox, |b, mailbox| {
+            b.to_async(&runtime).iter(|| mailbox.list_emails())
+        });
+    }
+    group.finish();
+}
+
+/// RETRIEVEs of concurrent clients by number of clients and mailbox size
+fn concurrent_retrieve(c: &mut Criterion) {
+    let runtime = runtime();
+    let mut group = c.benchmark_group("concurrent_retrieve");
+    for size in SIZES {
+        let mailbox = mailbox(&runtime, size);
+        for clients in CLIENTS {
+            group.throughput(Throughput::Elements(clients as u64 * COMMANDS));
+            let id = BenchmarkId::new(size.to_string(), clients);
+            group.bench_function(id, |b| {
+                b.iter_custom(|iterations| {
+                    concurrently(
+                        &runtime,
+                        iterations,
+                        clients,
+                        &mailbox,
+                        |mailbox, _| async move {
+                            mailbox.list_emails().await;
+                        },
+                    )
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+/// Concurrent clients sending one PUBLISH per nine RETRIEVEs, by number of clients
+fn mixed(c: &mut Criterion) {
+    let runtime = runtime();
+    let mut group = c.benchmark_group("mixed");
+    let mailbox = mailbox(&runtime, 1_000);
+    for clients in CLIENTS {
+        group.throughput(Throughput::Elements(clients as u64 * COMMANDS));
+        group.bench_function(BenchmarkId::from_parameter(clients), |b| {
+            b.iter_custom(|iterations| {
+                concurrently(
+                    &runtime,
+                    iterations,
+                    clients,
+                    &mailbox,
+                    |mailbox, i| async move {
+                        if i % 10 == 0 {
+                            let publish: Publish = "x".repeat(64).as_str().into();
+                            mailbox.append(&sender(), &publish).await.unwrap();
+                        } else {
+                            mailbox.list_emails().await;
+                        }
+                    },
+                )
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, retrieve, concurrent_retrieve, mixed);
+criterion_main!(benches);
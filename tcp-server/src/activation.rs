@@ -0,0 +1,158 @@
+use std::convert::TryFrom;
+use std::env;
+use std::io;
+use std::mem;
+use std::net::TcpListener;
+use std::ops::Range;
+use std::os::raw::{c_int, c_void};
+use std::os::unix::io::{FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::net::UnixListener;
+use std::process;
+
+/// The first file descriptor passed by systemd, `SD_LISTEN_FDS_START`
+const LISTEN_FDS_START: RawFd = 3;
+
+/// A listening socket passed by the service manager
+pub enum Activated {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+/// Takes the listening sockets passed with the `LISTEN_FDS` convention of systemd socket activation.
+/// Returns no sockets if the variables are not set or meant for another process.
+/// The variables are removed, so they are not inherited by child processes.
+///
+/// # Safety
+///
+/// Changing the environment is only sound while no other thread exists, so this must be called
+/// at the start of `main`, before the runtime is built.
+pub unsafe fn listeners() -> io::Result<Vec<Activated>> {
+    let count = listen_fds(
+        env::var("LISTEN_PID").ok().as_deref(),
+        env::var("LISTEN_FDS").ok().as_deref(),
+        process::id(),
+    )?;
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+    fds(count)?
+        // Safety: the service manager passes these file descriptors to this process and nothing else owns them
+        .map(|fd| unsafe { activated(fd) })
+        .collect()
+}
+
+/// Number of passed file descriptors
+fn listen_fds(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> io::Result<usize> {
+    match (listen_pid, listen_fds) {
+        (Some(listen_pid), Some(listen_fds)) if listen_pid == pid.to_string() => {
+            listen_fds.parse().map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid LISTEN_FDS: {}", listen_fds),
+                )
+            })
+        }
+        _ => Ok(0),
+    }
+}
+
+/// The passed file descriptors, an error if there are more than file descriptors can number
+fn fds(count: usize) -> io::Result<Range<RawFd>> {
+    RawFd::try_from(count)
+        .ok()
+        .and_then(|count| LISTEN_FDS_START.checked_add(count))
+        .map(|end| LISTEN_FDS_START..end)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid LISTEN_FDS: {}", count),
+            )
+        })
+}
+
+/// Tells TCP and Unix domain sockets apart by their local address
+unsafe fn activated(fd: RawFd) -> io::Result<Activated> {
+    check_listening_stream(fd)?;
+    let listener = TcpListener::from_raw_fd(fd);
+    if listener.local_addr().is_ok() {
+        listener.set_nonblocking(true)?;
+        return Ok(Activated::Tcp(listener));
+    }
+    let listener = UnixListener::from_raw_fd(listener.into_raw_fd());
+    listener.local_addr()?;
+    listener.set_nonblocking(true)?;
+    Ok(Activated::Unix(listener))
+}
+
+/// Rejects e.g. a datagram or a connected socket passed by a misconfigured socket unit,
+/// which would otherwise only fail on the first accept
+fn check_listening_stream(fd: RawFd) -> io::Result<()> {
+    let invalid = |reason| {
+        let message = format!("file descriptor {} is {}", fd, reason);
+        io::Error::new(io::ErrorKind::InvalidInput, message)
+    };
+    if socket_option(fd, libc::SO_TYPE).map_err(|_| invalid("not a socket"))? != libc::SOCK_STREAM {
+        return Err(invalid("not a stream socket"));
+    }
+    if socket_option(fd, libc::SO_ACCEPTCONN)? == 0 {
+        return Err(invalid("not a listening socket"));
+    }
+    Ok(())
+}
+
+fn socket_option(fd: RawFd, option: c_int) -> io::Result<c_int> {
+    let mut value: c_int = 0;
+    let mut len = mem::size_of::<c_int>() as libc::socklen_t;
+    // Safety: value and len are valid for writes and len is the size of value
+    let result = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            option,
+            &mut value as *mut c_int as *mut c_void,
+            &mut len,
+        )
+    };
+    match result {
+        0 => Ok(value),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::net::{TcpStream, UdpSocket};
+    use std::os::unix::io::AsRawFd;
+
+    use super::*;
+
+    #[test]
+    fn counts_file_descriptors_meant_for_this_process() {
+        assert_eq!(listen_fds(Some("42"), Some("2"), 42).unwrap(), 2);
+        assert_eq!(listen_fds(Some("41"), Some("2"), 42).unwrap(), 0);
+        assert_eq!(listen_fds(None, Some("2"), 42).unwrap(), 0);
+        assert_eq!(listen_fds(None, None, 42).unwrap(), 0);
+        assert!(listen_fds(Some("42"), Some("two"), 42).is_err());
+    }
+
+    #[test]
+    fn file_descriptors_start_at_three() {
+        assert_eq!(fds(2).unwrap(), 3..5);
+        assert_eq!(fds(0).unwrap(), 3..3);
+        assert!(fds(RawFd::MAX as usize).is_err());
+        assert!(fds(usize::MAX).is_err());
+    }
+
+    #[test]
+    fn accepts_only_listening_stream_sockets() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        assert!(check_listening_stream(listener.as_raw_fd()).is_ok());
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        assert!(check_listening_stream(stream.as_raw_fd()).is_err());
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        assert!(check_listening_stream(socket.as_raw_fd()).is_err());
+        let file = File::open("Cargo.toml").unwrap();
+        assert!(check_listening_stream(file.as_raw_fd()).is_err());
+    }
+}
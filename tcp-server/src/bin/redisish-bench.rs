@@ -0,0 +1,240 @@
+//! Load generator for the redisish protocol: concurrent connections send a mix of PUBLISH and
+//! RETRIEVE and the throughput and latency percentiles are reported per command
+
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use hdrhistogram::Histogram;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::task::JoinSet;
+
+use tcp_server::mailbox::{EvictionPolicy, VecDequeMailbox};
+use tcp_server::{blocking, Server};
+
+/// Redisish load generator
+#[derive(Parser, Debug)]
+struct Config {
+    /// Address of a running server, otherwise a server is started in-process
+    #[arg(long)]
+    server: Option<SocketAddr>,
+
+    /// Starts the blocking server in-process instead of the tokio server
+    #[arg(long, conflicts_with = "server")]
+    blocking: bool,
+
+    /// Capacity of the mailbox of the in-process server
+    #[arg(long, default_value_t = 1_000, conflicts_with = "server")]
+    capacity: usize,
+
+    /// Number of concurrent connections
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    connections: u64,
+
+    /// Seconds to send commands for
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u64).range(1..))]
+    duration: u64,
+
+    /// Share of PUBLISH among the commands from 0 to 1, the rest are RETRIEVE
+    #[arg(long, default_value_t = 0.5, value_parser = parse_ratio)]
+    publish_ratio: f64,
+
+    /// Size of the published message bodies in bytes
+    #[arg(long, default_value_t = 64)]
+    payload_size: usize,
+}
+
+/// Latencies in microseconds of the PUBLISH and RETRIEVE commands of a connection
+struct Latencies {
+    publish: Histogram<u64>,
+    retrieve: Histogram<u64>,
+}
+
+impl Latencies {
+    fn new() -> Latencies {
+        // up to a minute with 3 significant digits
+        let histogram = || Histogram::new_with_bounds(1, 60_000_000, 3).unwrap();
+        Latencies {
+            publish: histogram(),
+            retrieve: histogram(),
+        }
+    }
+
+    fn add(&mut self, other: &Latencies) {
+        self.publish.add(&other.publish).unwrap();
+        self.retrieve.add(&other.retrieve).unwrap();
+    }
+}
+
+/// Interleaves PUBLISH and RETRIEVE evenly at the given ratio, so runs are comparable
+struct Mix {
+    publish_ratio: f64,
+    credit: f64,
+}
+
+impl Mix {
+    fn new(publish_ratio: f64) -> Mix {
+        Mix {
+            publish_ratio,
+            credit: 0.0,
+        }
+    }
+
+    /// Whether the next command is a PUBLISH
+    fn next_is_publish(&mut self) -> bool {
+        self.credit += self.publish_ratio;
+        if self.credit >= 1.0 {
+            self.credit -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    let config = Config::parse();
+    let mut _server = None;
+    let addr = match config.server {
+        Some(addr) => addr,
+        None => {
+            let mailbox = Arc::new(VecDequeMailbox::new(
+                config.capacity,
+                EvictionPolicy::DropOldest,
+            ));
+            if config.blocking {
+                let listener = TcpListener::bind("127.0.0.1:0")?;
+                let addr = listener.local_addr()?;
+                let sweep_interval = Duration::from_secs(1);
+                thread::spawn(move || blocking::serve(listener, mailbox, sweep_interval));
+                addr
+            } else {
+                let server = Server::builder()
+                    .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+                    .mailbox(mailbox)
+                    .start()
+                    .await?;
+                let addr = server.local_addr().unwrap();
+                _server = Some(server);
+                addr
+            }
+        }
+    };
+
+    let payload = "x".repeat(config.payload_size);
+    let publish = Arc::new(format!("PUBLISH {}\n", payload));
+    let duration = Duration::from_secs(config.duration);
+    let started = Instant::now();
+    let mut connections = JoinSet::new();
+    for _ in 0..config.connections {
+        let publish = publish.clone();
+        let mix = Mix::new(config.publish_ratio);
+        connections.spawn(run_connection(addr, publish, mix, started + duration));
+    }
+    let mut latencies = Latencies::new();
+    while let Some(result) = connections.join_next().await {
+        latencies.add(&result??);
+    }
+    let elapsed = started.elapsed();
+
+    println!(
+        "{} connections, {:.0}% PUBLISH of {} bytes, {:.2} s",
+        config.connections,
+        config.publish_ratio * 100.0,
+        config.payload_size,
+        elapsed.as_secs_f64()
+    );
+    report("PUBLISH", &latencies.publish, elapsed);
+    report("RETRIEVE", &latencies.retrieve, elapsed);
+    let mut total = latencies.publish.clone();
+    total.add(&latencies.retrieve).unwrap();
+    report("total", &total, elapsed);
+    Ok(())
+}
+
+/// Sends commands until the deadline and measures the time until each reply arrived
+async fn run_connection(
+    addr: SocketAddr,
+    publish: Arc<String>,
+    mut mix: Mix,
+    deadline: Instant,
+) -> io::Result<Latencies> {
+    let mut stream = BufReader::new(TcpStream::connect(addr).await?);
+    let mut latencies = Latencies::new();
+    let mut reply = String::new();
+    while Instant::now() < deadline {
+        let is_publish = mix.next_is_publish();
+        let command = if is_publish {
+            publish.as_str()
+        } else {
+            "RETRIEVE\n"
+        };
+        let sent = Instant::now();
+        stream.get_mut().write_all(command.as_bytes()).await?;
+        reply.clear();
+        if stream.read_line(&mut reply).await? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let micros = sent.elapsed().as_micros() as u64;
+        if is_publish {
+            if !reply.starts_with("OK") {
+                return Err(io::Error::other(format!(
+                    "PUBLISH failed: {}",
+                    reply.trim()
+                )));
+            }
+            latencies.publish.saturating_record(micros);
+        } else {
+            latencies.retrieve.saturating_record(micros);
+        }
+    }
+    Ok(latencies)
+}
+
+fn report(name: &str, latencies: &Histogram<u64>, elapsed: Duration) {
+    let millis = |quantile: f64| latencies.value_at_quantile(quantile) as f64 / 1000.0;
+    println!(
+        "{:<8} {:>9} requests {:>10.0} req/s  p50 {:.3} ms  p99 {:.3} ms  p999 {:.3} ms  max {:.3} ms",
+        name,
+        latencies.len(),
+        latencies.len() as f64 / elapsed.as_secs_f64(),
+        millis(0.5),
+        millis(0.99),
+        millis(0.999),
+        latencies.max() as f64 / 1000.0,
+    );
+}
+
+fn parse_ratio(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(ratio) if (0.0..=1.0).contains(&ratio) => Ok(ratio),
+        _ => Err(format!(
+            "invalid ratio {}, expected a number from 0 to 1",
+            s
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mix_interleaves_commands_at_the_ratio() {
+        let mut mix = Mix::new(0.25);
+        let publishes: Vec<_> = (0..8).map(|_| mix.next_is_publish()).collect();
+        assert_eq!(
+            publishes,
+            [false, false, false, true, false, false, false, true]
+        );
+        assert!((0..10).all(|_| !Mix::new(0.0).next_is_publish()));
+        let mut mix = Mix::new(1.0);
+        assert!((0..10).all(|_| mix.next_is_publish()));
+        assert!(parse_ratio("1.5").is_err());
+    }
+}
@@ -0,0 +1,56 @@
+//! The redisish server on blocking sockets with a thread per connection,
+//! e.g. to compare it with the tokio server
+
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::Parser;
+
+use tcp_server::blocking;
+use tcp_server::logging::{self, LogFormat};
+use tcp_server::mailbox::{EvictionPolicy, VecDequeMailbox};
+
+/// Redisish tcp server with blocking I/O
+#[derive(Parser, Debug)]
+struct Config {
+    /// Address of the redisish listener
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    bind: SocketAddr,
+
+    /// Maximum number of emails kept in the mailbox
+    #[arg(long, default_value_t = 10_000, value_parser = clap::value_parser!(u64).range(1..))]
+    capacity: u64,
+
+    /// What to do when the mailbox is full: drop-oldest, reject-new or max-age=<seconds>
+    #[arg(long, default_value = "drop-oldest")]
+    eviction: EvictionPolicy,
+
+    /// Milliseconds between sweeps which drop emails whose time to live ran out
+    #[arg(long, default_value_t = 1_000, value_parser = clap::value_parser!(u64).range(1..))]
+    sweep_interval: u64,
+
+    /// Log level filter, e.g. `debug` or `info,tcp_server=trace`
+    #[arg(long, default_value = "info")]
+    log_level: String,
+
+    /// Log output format: text or json
+    #[arg(long, default_value = "text")]
+    log_format: LogFormat,
+}
+
+fn main() -> io::Result<()> {
+    let config = Config::parse();
+    logging::init(&config.log_level, config.log_format)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    let mailbox = Arc::new(VecDequeMailbox::new(
+        config.capacity as usize,
+        config.eviction,
+    ));
+    blocking::serve(
+        TcpListener::bind(config.bind)?,
+        mailbox,
+        Duration::from_millis(config.sweep_interval),
+    )
+}
@@ -0,0 +1,199 @@
+//! The redisish server on blocking `std::net` sockets with a thread per connection.
+//! It shares the mailbox and the command execution with the tokio server, but needs no
+//! async runtime: the few mailbox operations which wait, e.g. for a subscribed email,
+//! park the calling thread, see [block_on].
+
+use std::future::Future;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::pin::pin;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+use std::time::Duration;
+
+use tracing::{debug, info, warn};
+
+use redisish::parse;
+
+use crate::connection::{connection_span, execute};
+use crate::mailbox::{Sender, Subscriber, VecDequeMailbox};
+use crate::stats::Stats;
+
+/// How long the listener pauses after accepting a connection failed
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Accepts connections and serves each in its own thread.
+/// Emails whose time to live ran out are dropped every `sweep_interval`.
+pub fn serve(
+    listener: TcpListener,
+    mailbox: Arc<VecDequeMailbox>,
+    sweep_interval: Duration,
+) -> io::Result<()> {
+    info!(addr = %listener.local_addr()?, "Listening");
+    let stats = Arc::new(Stats::new());
+    {
+        let mailbox = mailbox.clone();
+        thread::spawn(move || sweep(&mailbox, sweep_interval));
+    }
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                warn!(%err, "Accepting a connection failed");
+                thread::sleep(ACCEPT_BACKOFF);
+                continue;
+            }
+        };
+        // fails if the client already reset the connection
+        let peer_addr = match stream.peer_addr() {
+            Ok(peer_addr) => peer_addr,
+            Err(err) => {
+                warn!(%err, "Connection failed");
+                continue;
+            }
+        };
+        let sender = Sender {
+            peer_addr: peer_addr.into(),
+            client_name: None,
+        };
+        let (connection_id, span) = connection_span(&sender.peer_addr);
+        let (mailbox, stats) = (mailbox.clone(), stats.clone());
+        thread::spawn(move || {
+            let _entered = span.enter();
+            info!("Client connected");
+            stats.client_connected();
+            let result = handle_client(stream, connection_id, sender, mailbox, &stats);
+            stats.client_disconnected();
+            match result {
+                Ok(()) => info!("Client disconnected"),
+                Err(err) => warn!(%err, "Connection failed"),
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Executes the commands of the client until it disconnects. Subscribed emails are written
+/// by a second thread, so both threads share the writing half of the connection.
+fn handle_client(
+    stream: TcpStream,
+    connection_id: u64,
+    sender: Sender,
+    mailbox: Arc<VecDequeMailbox>,
+    stats: &Stats,
+) -> io::Result<()> {
+    let subscriber = block_on(mailbox.subscriber());
+    let client = stats
+        .clients()
+        .connect(connection_id, sender, subscriber.clone());
+    let writer = Arc::new(Mutex::new(stream.try_clone()?));
+    let pusher = {
+        let (subscriber, writer) = (subscriber.clone(), writer.clone());
+        thread::spawn(move || push(&subscriber, &writer))
+    };
+    let mut reader = BufReader::new(stream);
+    let mut line = Vec::new();
+    let result = loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) => break Ok(()),
+            Ok(_) => {}
+            Err(err) => break Err(err),
+        }
+        let str = String::from_utf8(std::mem::take(&mut line)).unwrap_or_default();
+        let reply = match parse(&str) {
+            Ok(command) => block_on(execute(command, &client, &mailbox, stats)),
+            Err(err) => {
+                stats.parse_error(&err);
+                warn!(%err, "Client error");
+                break Ok(());
+            }
+        };
+        if let Err(err) = lock(&writer).write_all(reply.as_bytes()) {
+            break Err(err);
+        }
+    };
+    if client.is_killed() {
+        info!("Killed by CLIENT KILL");
+    }
+    stats.clients().disconnect(connection_id);
+    block_on(mailbox.unsubscribe(&subscriber));
+    subscriber.disconnect();
+    pusher.join().unwrap();
+    result
+}
+
+/// Writes the emails of the subscribed channels until the subscriber is disconnected,
+/// then closes the connection, e.g. if the subscriber did not keep up or was killed
+fn push(subscriber: &Subscriber, writer: &Mutex<TcpStream>) {
+    while let Some(email) = block_on(subscriber.next()) {
+        let message = format!("MESSAGE {}\n", email);
+        if lock(writer).write_all(message.as_bytes()).is_err() {
+            break;
+        }
+    }
+    let _ = lock(writer).shutdown(Shutdown::Both);
+}
+
+/// Locks the writing half, also if a panic poisoned the lock,
+/// so the other thread of the connection can still write and close it
+fn lock(writer: &Mutex<TcpStream>) -> MutexGuard<'_, TcpStream> {
+    writer.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Periodically drops emails whose time to live ran out, even if nobody reads the mailbox
+fn sweep(mailbox: &VecDequeMailbox, interval: Duration) {
+    loop {
+        thread::sleep(interval);
+        let expired = block_on(mailbox.sweep());
+        if expired > 0 {
+            debug!(expired, "Swept expired emails");
+        }
+    }
+}
+
+/// Wakes up the thread which waits in [block_on]
+struct Unpark(Thread);
+
+impl Wake for Unpark {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Runs a future on the current thread, parking the thread while the future waits
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut context = Context::from_waker(&waker);
+    loop {
+        match future.as_mut().poll(&mut context) {
+            Poll::Ready(output) => return output,
+            // unpark before park makes park return right away, so no wake up is lost
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use event_listener::Event;
+
+    use super::*;
+
+    #[test]
+    fn block_on_waits_for_a_wake_up_from_another_thread() {
+        let event = Arc::new(Event::new());
+        let listener = event.listen();
+        let notifier = {
+            let event = event.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                event.notify(1);
+            })
+        };
+        block_on(listener);
+        notifier.join().unwrap();
+    }
+}
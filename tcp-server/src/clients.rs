@@ -0,0 +1,200 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Instant;
+
+use crate::mailbox::{Sender, Subscriber};
+
+/// A connected client as listed by CLIENT LIST
+pub struct Client {
+    /// The connection id of the log events
+    pub id: u64,
+    connected: Instant,
+    subscriber: Arc<Subscriber>,
+    state: Mutex<State>,
+}
+
+struct State {
+    sender: Sender,
+    last_command: Instant,
+    commands: u64,
+    killed: bool,
+}
+
+/// The connected clients of a server, shared by all its listeners
+#[derive(Default)]
+pub struct Clients {
+    clients: Mutex<BTreeMap<u64, Arc<Client>>>,
+}
+
+/// Error of CLIENT KILL
+#[derive(Eq, PartialEq, Debug)]
+pub enum Error {
+    UnknownClient(u64),
+}
+
+impl Client {
+    /// The sender of the emails published by the client, including its name
+    pub fn sender(&self) -> Sender {
+        self.state().sender.clone()
+    }
+
+    pub fn subscriber(&self) -> &Arc<Subscriber> {
+        &self.subscriber
+    }
+
+    /// Names the client, the name is stored with the emails it publishes from now on
+    pub fn set_name(&self, name: String) {
+        self.state().sender.client_name = Some(name);
+    }
+
+    /// Counts a command and resets the idle time
+    pub fn command(&self) {
+        let mut state = self.state();
+        state.last_command = Instant::now();
+        state.commands += 1;
+    }
+
+    /// Whether the connection closes because of CLIENT KILL
+    pub fn is_killed(&self) -> bool {
+        self.state().killed
+    }
+
+    /// Locks the state, also if a panic poisoned the lock,
+    /// so one failing connection does not fail CLIENT commands for good
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// `<id> <peer_addr> <name|-> <age> <idle> <commands>`, age and idle time in seconds
+impl fmt::Display for Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state();
+        write!(
+            f,
+            "{} {} {} {} {} {}",
+            self.id,
+            state.sender.peer_addr,
+            state.sender.client_name.as_deref().unwrap_or("-"),
+            self.connected.elapsed().as_secs(),
+            state.last_command.elapsed().as_secs(),
+            state.commands
+        )
+    }
+}
+
+impl Clients {
+    /// Registers a connection, the subscriber is disconnected to close it on CLIENT KILL
+    pub fn connect(&self, id: u64, sender: Sender, subscriber: Arc<Subscriber>) -> Arc<Client> {
+        let now = Instant::now();
+        let client = Arc::new(Client {
+            id,
+            connected: now,
+            subscriber,
+            state: Mutex::new(State {
+                sender,
+                last_command: now,
+                commands: 0,
+                killed: false,
+            }),
+        });
+        self.clients().insert(id, client.clone());
+        client
+    }
+
+    pub fn disconnect(&self, id: u64) {
+        self.clients().remove(&id);
+    }
+
+    /// The connected clients ordered by id
+    pub fn list(&self) -> Vec<Arc<Client>> {
+        self.clients().values().cloned().collect()
+    }
+
+    /// Closes the connection of the client, like the disconnect of a slow subscriber
+    pub fn kill(&self, id: u64) -> Result<(), Error> {
+        let client = self
+            .clients()
+            .get(&id)
+            .cloned()
+            .ok_or(Error::UnknownClient(id))?;
+        client.state().killed = true;
+        client.subscriber.disconnect();
+        Ok(())
+    }
+
+    /// Locks the clients, also if a panic poisoned the lock,
+    /// so one failing connection does not fail registering later connections
+    fn clients(&self) -> MutexGuard<'_, BTreeMap<u64, Arc<Client>>> {
+        self.clients.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UnknownClient(id) => write!(f, "Client error, unknown client: {}", id),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use crate::mailbox::tests::sender;
+    use crate::mailbox::{EvictionPolicy, VecDequeMailbox};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn lists_names_and_kills_clients() {
+        let mailbox = VecDequeMailbox::new(10, EvictionPolicy::DropOldest);
+        let clients = Clients::default();
+        let first = clients.connect(1, sender(), mailbox.subscriber().await);
+        let second = clients.connect(2, sender(), mailbox.subscriber().await);
+        first.set_name("tui".to_owned());
+        first.command();
+        first.command();
+
+        let list: Vec<_> = clients.list().iter().map(|it| it.to_string()).collect();
+        assert_eq!(
+            list,
+            ["1 127.0.0.1:4000 tui 0 0 2", "2 127.0.0.1:4000 - 0 0 0"]
+        );
+        assert_eq!(first.sender().client_name.as_deref(), Some("tui"));
+
+        assert_eq!(clients.kill(2), Ok(()));
+        assert!(second.is_killed());
+        assert_eq!(second.subscriber().next().await, None);
+        clients.disconnect(2);
+        assert_eq!(clients.kill(2), Err(Error::UnknownClient(2)));
+        assert!(!first.is_killed());
+        assert_eq!(clients.list().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn clients_keep_working_after_a_panic_while_locked() {
+        let mailbox = VecDequeMailbox::new(10, EvictionPolicy::DropOldest);
+        let clients = Arc::new(Clients::default());
+        let client = clients.connect(1, sender(), mailbox.subscriber().await);
+        let (panicking, panicking_client) = (clients.clone(), client.clone());
+        let result = std::thread::spawn(move || {
+            let _clients = panicking.clients();
+            let _state = panicking_client.state();
+            panic!("bug while the locks are held");
+        })
+        .join();
+        assert!(result.is_err());
+        assert!(clients.clients.is_poisoned());
+        assert!(client.state.is_poisoned());
+
+        client.set_name("tui".to_owned());
+        clients.connect(2, sender(), mailbox.subscriber().await);
+        assert_eq!(clients.kill(1), Ok(()));
+        clients.disconnect(2);
+        let list: Vec<_> = clients.list().iter().map(|it| it.to_string()).collect();
+        assert_eq!(list, ["1 127.0.0.1:4000 tui 0 0 0"]);
+    }
+}
@@ -0,0 +1,114 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use clap::Parser;
+
+use tcp_server::logging::LogFormat;
+use tcp_server::mailbox::{EvictionPolicy, SlowSubscriberPolicy, MAX_DURATION_SECS};
+
+/// Redisish tcp server
+#[derive(Parser, Debug)]
+pub struct Config {
+    /// Address of the redisish listener, not bound if systemd passes listening sockets
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    pub bind: SocketAddr,
+
+    /// PEM file with the certificate chain, enables TLS on the redisish listener
+    #[arg(long, requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM file with the private key of the certificate
+    #[arg(long, requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Path of a Unix domain socket to listen on in addition to the TCP address,
+    /// not bound if systemd passes listening sockets
+    #[cfg(unix)]
+    #[arg(long)]
+    pub unix_socket: Option<PathBuf>,
+
+    /// Permissions of the Unix domain socket in octal, e.g. 660, otherwise they depend on the umask
+    #[cfg(unix)]
+    #[arg(long, value_parser = parse_mode, requires = "unix_socket")]
+    pub unix_socket_mode: Option<u32>,
+
+    /// Address of the HTTP/JSON and WebSocket gateway to the mailbox, disabled if not set
+    #[arg(long)]
+    pub http_addr: Option<SocketAddr>,
+
+    /// Address of the HTTP listener serving Prometheus metrics at /metrics, disabled if not set
+    #[arg(long)]
+    pub metrics_addr: Option<SocketAddr>,
+
+    /// Maximum number of emails kept in the mailbox
+    #[arg(long, default_value_t = 10_000, value_parser = clap::value_parser!(u64).range(1..))]
+    pub capacity: u64,
+
+    /// What to do when the mailbox is full: drop-oldest, reject-new or max-age=<seconds>
+    #[arg(long, default_value = "drop-oldest")]
+    pub eviction: EvictionPolicy,
+
+    /// Seconds a fetched email stays invisible to other consumers until it is acknowledged
+    #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(u64).range(..=MAX_DURATION_SECS))]
+    pub visibility_timeout: u64,
+
+    /// How often an email may be NACKed or time out before it moves to the dead-letter queue
+    #[arg(long, default_value_t = 5)]
+    pub max_failures: u32,
+
+    /// Milliseconds between sweeps which drop emails whose time to live ran out
+    #[arg(long, default_value_t = 1_000, value_parser = clap::value_parser!(u64).range(1..))]
+    pub sweep_interval: u64,
+
+    /// Seconds an idempotency key of PUBLISH is remembered to drop retried duplicates
+    #[arg(long, default_value_t = 300)]
+    pub dedup_window: u64,
+
+    /// Maximum number of remembered idempotency keys
+    #[arg(long, default_value_t = 10_000)]
+    pub dedup_capacity: usize,
+
+    /// Maximum number of committed consumer group offsets, the least recently used is forgotten
+    #[arg(long, default_value_t = 1_000, value_parser = clap::value_parser!(u64).range(1..))]
+    pub max_groups: u64,
+
+    /// Maximum number of emails buffered for a subscriber which does not keep up
+    #[arg(long, default_value_t = 1_000, value_parser = clap::value_parser!(u64).range(1..))]
+    pub subscriber_buffer: u64,
+
+    /// What to do when a subscriber's buffer is full: drop-oldest, disconnect or backpressure
+    #[arg(long, default_value = "drop-oldest")]
+    pub slow_subscriber: SlowSubscriberPolicy,
+
+    /// Milliseconds a publisher waits for a subscriber with the backpressure policy
+    /// before the subscriber is disconnected
+    #[arg(
+        long,
+        default_value_t = 5_000,
+        value_parser = clap::value_parser!(u64).range(1..=MAX_DURATION_SECS * 1_000)
+    )]
+    pub backpressure_timeout: u64,
+
+    /// Print the INFO statistics every given number of seconds
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub info_interval: Option<u64>,
+
+    /// Log level filter, e.g. `debug` or `info,tcp_server=trace`
+    #[arg(long, default_value = "info")]
+    pub log_level: String,
+
+    /// Log output format: text or json
+    #[arg(long, default_value = "text")]
+    pub log_format: LogFormat,
+}
+
+#[cfg(unix)]
+fn parse_mode(s: &str) -> Result<u32, String> {
+    match u32::from_str_radix(s, 8) {
+        Ok(mode) if mode <= 0o777 => Ok(mode),
+        _ => Err(format!(
+            "invalid mode {}, expected octal permissions like 660",
+            s
+        )),
+    }
+}
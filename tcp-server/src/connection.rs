@@ -0,0 +1,217 @@
+use std::fmt;
+#[cfg(feature = "async")]
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "async")]
+use std::sync::Arc;
+use std::time::Instant;
+
+#[cfg(feature = "async")]
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+#[cfg(feature = "async")]
+use tracing::info;
+use tracing::{debug, info_span, warn, Span};
+
+#[cfg(feature = "async")]
+use redisish::parse;
+use redisish::{Client as ClientCommand, Command, Retrieve};
+
+use crate::clients::Client;
+#[cfg(feature = "async")]
+use crate::mailbox::Sender;
+use crate::mailbox::{sort_by_priority, PeerAddr, VecDequeMailbox};
+use crate::stats::Stats;
+
+/// Numbers the connections of all listeners to tell them apart in the logs
+static CONNECTION_IDS: AtomicU64 = AtomicU64::new(1);
+
+/// Numbers the connection and creates the span of its log events.
+/// The number is the id of the client in CLIENT LIST.
+pub(crate) fn connection_span(peer_addr: &PeerAddr) -> (u64, Span) {
+    let connection_id = CONNECTION_IDS.fetch_add(1, Ordering::Relaxed);
+    (
+        connection_id,
+        info_span!("connection", connection_id, %peer_addr),
+    )
+}
+
+/// TODO how can I pass the mailbox, preferably just mailbox: Mailbox or at least mailbox: Arc<Box<dyn Mailbox>>?
+#[cfg(feature = "async")]
+pub(crate) async fn handle_client<S: AsyncRead + AsyncWrite + Unpin>(
+    mut tcp_stream: BufReader<S>,
+    connection_id: u64,
+    sender: Sender,
+    mailbox: Arc<VecDequeMailbox>,
+    stats: &Stats,
+) -> Result<(), io::Error> {
+    let subscriber = mailbox.subscriber().await;
+    let client = stats
+        .clients()
+        .connect(connection_id, sender, subscriber.clone());
+    let mut line = Vec::new();
+    let result = loop {
+        // read_until keeps a partially read line in the buffer when a pushed email wins the race
+        let reply = tokio::select! {
+            read = tcp_stream.read_until(b'\n', &mut line) => {
+                if let Ok(0) = read {
+                    break Ok(());
+                }
+                let str = String::from_utf8(std::mem::take(&mut line)).unwrap_or_default();
+                match parse(&str) {
+                    Ok(command) => execute(command, &client, &mailbox, stats).await,
+                    Err(err) => {
+                        stats.parse_error(&err);
+                        warn!(%err, "Client error");
+                        break Ok(());
+                    }
+                }
+            }
+            email = subscriber.next() => match email {
+                Some(email) => format!("MESSAGE {}\n", email),
+                None if client.is_killed() => {
+                    info!("Killed by CLIENT KILL");
+                    break Ok(());
+                }
+                None => {
+                    warn!("Disconnected slow subscriber");
+                    break Ok(());
+                }
+            },
+        };
+        if let Err(err) = tcp_stream.write_all(reply.as_ref()).await {
+            break Err(err);
+        }
+    };
+    stats.clients().disconnect(connection_id);
+    mailbox.unsubscribe(&subscriber).await;
+    result
+}
+
+/// Executes a command, counts it in the statistics and returns the reply.
+/// It only waits for the mailbox, so the blocking server runs it without an async runtime.
+pub(crate) async fn execute(
+    command: Command,
+    client: &Client,
+    mailbox: &VecDequeMailbox,
+    stats: &Stats,
+) -> String {
+    let started = Instant::now();
+    let verb = command.verb();
+    client.command();
+    let reply = match command {
+        Command::Publish(publish) => {
+            debug!(body = %publish.message, "Appending email");
+            let sender = client.sender();
+            match mailbox
+                .append_from(client.subscriber(), &sender, &publish)
+                .await
+            {
+                Ok(Some(id)) => format!("OK {}\n", id),
+                Ok(None) => "OK\n".to_owned(),
+                Err(err) => {
+                    warn!(%err, "Rejected email");
+                    format!("ERR {}\n", err)
+                }
+            }
+        }
+        Command::Retrieve(Retrieve {
+            with_meta: false,
+            ordered: false,
+            dead_letters: None,
+            since: None,
+        }) => format!("{}\n", mailbox.list_emails().await),
+        Command::Retrieve(retrieve) => {
+            let emails = match (&retrieve.dead_letters, retrieve.since) {
+                (None, None) => Ok(mailbox.list_messages().await),
+                (None, Some(since)) => Ok(mailbox.list_messages_since(since).await),
+                (Some(channel), since) => mailbox.dead_letters(channel).await.map(|mut emails| {
+                    emails.retain(|email| since.is_none_or(|since| email.id > since));
+                    emails
+                }),
+            };
+            match emails {
+                Ok(mut emails) => {
+                    if retrieve.ordered {
+                        sort_by_priority(&mut emails);
+                    }
+                    if retrieve.with_meta {
+                        lines(emails)
+                    } else {
+                        emails
+                            .iter()
+                            .fold(String::new(), |acc, next| acc + &next.body + ";")
+                            + "\n"
+                    }
+                }
+                Err(err) => format!("ERR {}\n", err),
+            }
+        }
+        Command::Channels => lines(
+            mailbox
+                .list_channels()
+                .await
+                .into_iter()
+                .map(|(channel, count)| format!("{} {}", channel, count))
+                .collect(),
+        ),
+        Command::Create(channel) => ok_or_err(mailbox.create_channel(&channel).await),
+        Command::Delete(channel) => ok_or_err(mailbox.delete_channel(&channel).await),
+        Command::Fetch(channel) => match mailbox.fetch(&channel).await {
+            Ok(email) => lines(email.into_iter().collect()),
+            Err(err) => format!("ERR {}\n", err),
+        },
+        Command::Ack(id) => ok_or_err(mailbox.ack(id).await),
+        Command::Nack(id) => ok_or_err(mailbox.nack(id).await),
+        Command::Read {
+            group,
+            channel,
+            count,
+        } => match mailbox.read_group(&group, &channel, count).await {
+            Ok(emails) => lines(emails),
+            Err(err) => format!("ERR {}\n", err),
+        },
+        Command::Commit { group, channel, id } => {
+            ok_or_err(mailbox.commit(&group, &channel, id).await)
+        }
+        Command::Search {
+            channel,
+            mode,
+            query,
+        } => match mailbox.search(&channel, mode, &query).await {
+            Ok(emails) => lines(emails),
+            Err(err) => format!("ERR {}\n", err),
+        },
+        Command::Replay(channel) => match mailbox.replay(&channel).await {
+            Ok(replayed) => format!("OK {}\n", replayed),
+            Err(err) => format!("ERR {}\n", err),
+        },
+        Command::Subscribe(channel) => {
+            ok_or_err(mailbox.subscribe(client.subscriber(), &channel).await)
+        }
+        Command::Info => lines(stats.info(mailbox).await),
+        Command::Client(ClientCommand::SetName(name)) => {
+            client.set_name(name);
+            "OK\n".to_owned()
+        }
+        Command::Client(ClientCommand::List) => lines(stats.clients().list()),
+        Command::Client(ClientCommand::Kill(id)) => ok_or_err(stats.clients().kill(id)),
+    };
+    stats.command(verb, started.elapsed());
+    reply
+}
+
+/// Multi-line reply: number of lines followed by one item per line
+fn lines<T: fmt::Display>(items: Vec<T>) -> String {
+    let mut reply = format!("{}\n", items.len());
+    for item in items {
+        reply.push_str(&format!("{}\n", item));
+    }
+    reply
+}
+
+fn ok_or_err<E: fmt::Display>(result: Result<(), E>) -> String {
+    match result {
+        Ok(()) => "OK\n".to_owned(),
+        Err(err) => format!("ERR {}\n", err),
+    }
+}
@@ -0,0 +1,608 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use axum::async_trait;
+use axum::extract::ws::{Message as Frame, WebSocket, WebSocketUpgrade};
+use axum::extract::{
+    ConnectInfo, FromRef, FromRequest, FromRequestParts, Path, Query, Request, State,
+};
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::de::{self, DeserializeOwned};
+use serde::{Deserialize, Deserializer, Serialize};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tracing::{info, warn, Instrument};
+
+use redisish::{parse, Command, Publish, Schedule};
+
+use crate::clients::Client;
+use crate::connection::{connection_span, execute};
+use crate::mailbox::{self, Sender, StoredMessage, VecDequeMailbox};
+use crate::server::shutdown_signal;
+use crate::stats::Stats;
+
+/// Serves the HTTP/JSON gateway until the server shuts down:
+/// * `POST /queues/{queue}/messages` publishes a message
+/// * `GET /queues/{queue}/messages?since=<id>` returns the messages after the id, oldest first
+/// * `GET /queues` lists the queues with their number of messages
+/// * `GET /ws` upgrades to a WebSocket, see [handle_websocket]
+pub async fn serve(
+    listener: TcpListener,
+    mailbox: Arc<VecDequeMailbox>,
+    stats: Arc<Stats>,
+    shutdown: watch::Receiver<bool>,
+) {
+    let app = router(mailbox, stats, shutdown.clone())
+        .into_make_service_with_connect_info::<SocketAddr>();
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal(shutdown))
+        .await
+        .unwrap();
+}
+
+fn router(
+    mailbox: Arc<VecDequeMailbox>,
+    stats: Arc<Stats>,
+    shutdown: watch::Receiver<bool>,
+) -> Router {
+    Router::new()
+        .route("/queues", get(list_queues))
+        .route(
+            "/queues/:queue/messages",
+            get(list_messages).post(publish_message),
+        )
+        .route("/ws", get(websocket))
+        .with_state(GatewayState {
+            mailbox,
+            stats,
+            shutdown,
+        })
+}
+
+#[derive(Clone)]
+struct GatewayState {
+    mailbox: Arc<VecDequeMailbox>,
+    stats: Arc<Stats>,
+    /// WebSocket connections outlive the HTTP server, so they watch for the shutdown themselves
+    shutdown: watch::Receiver<bool>,
+}
+
+impl FromRef<GatewayState> for Arc<VecDequeMailbox> {
+    fn from_ref(state: &GatewayState) -> Self {
+        state.mailbox.clone()
+    }
+}
+
+impl FromRef<GatewayState> for Arc<Stats> {
+    fn from_ref(state: &GatewayState) -> Self {
+        state.stats.clone()
+    }
+}
+
+/// [Json] body whose rejection is an [ErrorBody] like the other errors
+struct JsonBody<T>(T);
+
+/// [Query] string whose rejection is an [ErrorBody] like the other errors
+struct QueryString<T>(T);
+
+#[async_trait]
+impl<T: DeserializeOwned, S: Send + Sync> FromRequest<S> for JsonBody<T> {
+    type Rejection = Response;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        match Json::from_request(request, state).await {
+            Ok(Json(value)) => Ok(JsonBody(value)),
+            Err(rejection) => Err(error(rejection.status(), &rejection.body_text())),
+        }
+    }
+}
+
+#[async_trait]
+impl<T: DeserializeOwned, S: Send + Sync> FromRequestParts<S> for QueryString<T> {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        match Query::from_request_parts(parts, state).await {
+            Ok(Query(value)) => Ok(QueryString(value)),
+            Err(rejection) => Err(error(rejection.status(), &rejection.body_text())),
+        }
+    }
+}
+
+/// Body of a publish request, the optional fields are the PUBLISH options
+#[derive(Deserialize, Debug)]
+struct PublishRequest {
+    body: String,
+    #[serde(default)]
+    priority: u8,
+    delay_ms: Option<u64>,
+    ttl_secs: Option<u64>,
+    idempotency_key: Option<String>,
+}
+
+/// The id is missing if the message is delayed, it is assigned when the message becomes visible
+#[derive(Serialize, Debug)]
+struct Published {
+    id: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+struct Queue {
+    name: String,
+    messages: u64,
+}
+
+#[derive(Deserialize, Debug)]
+struct Since {
+    /// An empty `?since=` is treated as absent
+    #[serde(default, deserialize_with = "empty_as_zero")]
+    since: u64,
+}
+
+/// A [StoredMessage] with the times in milliseconds since the unix epoch
+#[derive(Serialize, Debug)]
+struct Message {
+    id: u64,
+    received_at: u128,
+    peer_addr: String,
+    client_name: Option<String>,
+    channel: String,
+    body: String,
+    priority: u8,
+    expires_at: Option<u128>,
+}
+
+/// Failed request, the status code depends on the error
+#[derive(Serialize, Debug)]
+struct ErrorBody {
+    error: String,
+}
+
+/// Publishes like PUBLISH and counts as PUBLISH in the statistics
+async fn publish_message(
+    State(mailbox): State<Arc<VecDequeMailbox>>,
+    State(stats): State<Arc<Stats>>,
+    ConnectInfo(peer_addr): ConnectInfo<SocketAddr>,
+    Path(queue): Path<String>,
+    JsonBody(request): JsonBody<PublishRequest>,
+) -> Response {
+    let started = Instant::now();
+    let publish = Publish {
+        channel: Some(queue),
+        schedule: request
+            .delay_ms
+            .map(|delay| Schedule::Delay(Duration::from_millis(delay))),
+        expire: request.ttl_secs.map(Duration::from_secs),
+        priority: request.priority,
+        idempotency_key: request.idempotency_key,
+        message: request.body,
+    };
+    let publish = match validate(Command::Publish(publish)) {
+        Ok(Command::Publish(publish)) => publish,
+        Ok(_) => unreachable!("the command was built as PUBLISH"),
+        Err(err) => {
+            stats.parse_error(&err);
+            return error(parse_error_status(&err), &err);
+        }
+    };
+    let sender = Sender {
+        peer_addr: peer_addr.into(),
+        client_name: None,
+    };
+    let response = match mailbox.append(&sender, &publish).await {
+        Ok(Some(id)) => (StatusCode::CREATED, Json(Published { id: Some(id) })).into_response(),
+        Ok(None) => (StatusCode::ACCEPTED, Json(Published { id: None })).into_response(),
+        Err(err) => error(mailbox_error_status(&err), &err),
+    };
+    stats.command("PUBLISH", started.elapsed());
+    response
+}
+
+async fn list_messages(
+    State(mailbox): State<Arc<VecDequeMailbox>>,
+    Path(queue): Path<String>,
+    QueryString(Since { since }): QueryString<Since>,
+) -> Response {
+    match mailbox.messages_since(&queue, since).await {
+        Ok(emails) => {
+            Json(emails.into_iter().map(Message::from).collect::<Vec<_>>()).into_response()
+        }
+        Err(err) => error(mailbox_error_status(&err), &err),
+    }
+}
+
+async fn list_queues(State(mailbox): State<Arc<VecDequeMailbox>>) -> Json<Vec<Queue>> {
+    let channels = mailbox.list_channels().await;
+    Json(
+        channels
+            .into_iter()
+            .map(|(name, messages)| Queue { name, messages })
+            .collect(),
+    )
+}
+
+async fn websocket(
+    State(GatewayState {
+        mailbox,
+        stats,
+        shutdown,
+    }): State<GatewayState>,
+    ConnectInfo(peer_addr): ConnectInfo<SocketAddr>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let sender = Sender {
+        peer_addr: peer_addr.into(),
+        client_name: None,
+    };
+    let (connection_id, span) = connection_span(&sender.peer_addr);
+    upgrade.on_upgrade(move |socket| {
+        async move {
+            info!("WebSocket client connected");
+            stats.client_connected();
+            let subscriber = mailbox.subscriber().await;
+            let client = stats.clients().connect(connection_id, sender, subscriber);
+            let result = handle_websocket(socket, &client, &mailbox, &stats, shutdown).await;
+            stats.clients().disconnect(connection_id);
+            mailbox.unsubscribe(client.subscriber()).await;
+            stats.client_disconnected();
+            match result {
+                Ok(()) => info!("WebSocket client disconnected"),
+                Err(err) => warn!(%err, "WebSocket connection failed"),
+            }
+        }
+        .instrument(span)
+    })
+}
+
+/// Serves a WebSocket client like a TCP client: every text frame is a command, the trailing
+/// newline is optional, and every reply or email pushed to a subscription is a text frame
+async fn handle_websocket(
+    mut socket: WebSocket,
+    client: &Client,
+    mailbox: &VecDequeMailbox,
+    stats: &Stats,
+    shutdown: watch::Receiver<bool>,
+) -> Result<(), axum::Error> {
+    let result = loop {
+        let reply = tokio::select! {
+            frame = socket.recv() => match frame {
+                Some(Ok(Frame::Text(mut line))) => {
+                    if !line.ends_with('\n') {
+                        line.push('\n');
+                    }
+                    match parse(&line) {
+                        Ok(command) => execute(command, client, mailbox, stats).await,
+                        Err(err) => {
+                            stats.parse_error(&err);
+                            warn!(%err, "Client error");
+                            break Ok(());
+                        }
+                    }
+                }
+                // pings are answered by the WebSocket implementation
+                Some(Ok(Frame::Binary(_) | Frame::Ping(_) | Frame::Pong(_))) => continue,
+                Some(Ok(Frame::Close(_))) | None => break Ok(()),
+                Some(Err(err)) => break Err(err),
+            },
+            email = client.subscriber().next() => match email {
+                Some(email) => format!("MESSAGE {}\n", email),
+                None if client.is_killed() => {
+                    info!("Killed by CLIENT KILL");
+                    break Ok(());
+                }
+                None => {
+                    warn!("Disconnected slow subscriber");
+                    break Ok(());
+                }
+            },
+            _ = shutdown_signal(shutdown.clone()) => break Ok(()),
+        };
+        if let Err(err) = socket.send(Frame::Text(reply)).await {
+            break Err(err);
+        }
+    };
+    // starts or completes the closing handshake, fails if the connection is gone already
+    let _ = socket.send(Frame::Close(None)).await;
+    result
+}
+
+/// Applies the rules of the line protocol to a command built from a request:
+/// it must survive the round trip through [Command::as_string] and [parse] unchanged,
+/// so e.g. a queue name with a space or a body with a newline is rejected
+fn validate(command: Command) -> Result<Command, redisish::Error> {
+    let line = command.as_string();
+    match parse(&line)? {
+        parsed if parsed == command => Ok(parsed),
+        _ => Err(redisish::Error::Malformed(format!(
+            "Malformed: {}",
+            line.trim_end()
+        ))),
+    }
+}
+
+/// The gateway builds the lines itself, so a missing newline or an unknown verb is its own fault
+fn parse_error_status(err: &redisish::Error) -> StatusCode {
+    match err {
+        redisish::Error::NewlineInMessage | redisish::Error::Malformed(_) => {
+            StatusCode::UNPROCESSABLE_ENTITY
+        }
+        redisish::Error::MissingNewline | redisish::Error::UnknownVerb => {
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+fn mailbox_error_status(err: &mailbox::Error) -> StatusCode {
+    match err {
+        mailbox::Error::Full => StatusCode::SERVICE_UNAVAILABLE,
+        mailbox::Error::UnknownChannel(_) | mailbox::Error::UnknownEmail(_) => {
+            StatusCode::NOT_FOUND
+        }
+        mailbox::Error::ChannelExists(_)
+        | mailbox::Error::DefaultChannel
+        | mailbox::Error::NotLeased(_) => StatusCode::CONFLICT,
+        mailbox::Error::InvalidQuery(_) | mailbox::Error::TimeOutOfRange => StatusCode::BAD_REQUEST,
+    }
+}
+
+fn error(status: StatusCode, err: &impl ToString) -> Response {
+    let body = ErrorBody {
+        error: err.to_string(),
+    };
+    (status, Json(body)).into_response()
+}
+
+fn empty_as_zero<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    match String::deserialize(deserializer)?.as_str() {
+        "" => Ok(0),
+        since => since.parse().map_err(de::Error::custom),
+    }
+}
+
+impl From<StoredMessage> for Message {
+    fn from(email: StoredMessage) -> Self {
+        let millis = |time: std::time::SystemTime| {
+            time.duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis()
+        };
+        Message {
+            id: email.id,
+            received_at: millis(email.received_at),
+            peer_addr: email.peer_addr.to_string(),
+            client_name: email.client_name,
+            channel: email.channel,
+            body: email.body,
+            priority: email.priority,
+            expires_at: email.expires_at.map(millis),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::{to_bytes, Body};
+    use axum::extract::connect_info::MockConnectInfo;
+    use axum::http::{header, Method, Request};
+    use futures_util::{SinkExt, StreamExt};
+    use serde_json::{json, Value};
+    use tokio_tungstenite::tungstenite::Message as WsMessage;
+    use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+    use tower::ServiceExt;
+
+    use crate::mailbox::EvictionPolicy;
+
+    use super::*;
+
+    async fn request(
+        mailbox: &Arc<VecDequeMailbox>,
+        method: Method,
+        uri: &str,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let body = body.map_or_else(Body::empty, |body| Body::from(body.to_string()));
+        send(mailbox, &Arc::new(Stats::new()), method, uri, body).await
+    }
+
+    async fn send(
+        mailbox: &Arc<VecDequeMailbox>,
+        stats: &Arc<Stats>,
+        method: Method,
+        uri: &str,
+        body: Body,
+    ) -> (StatusCode, Value) {
+        let app = router(mailbox.clone(), stats.clone(), watch::channel(false).1)
+            .layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4000))));
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn publishes_and_lists_messages() {
+        let mailbox = Arc::new(VecDequeMailbox::new(10, EvictionPolicy::DropOldest));
+        let publish = |body| request(&mailbox, Method::POST, "/queues/misc/messages", Some(body));
+
+        assert_eq!(
+            publish(json!({"body": "hello"})).await,
+            (StatusCode::CREATED, json!({"id": 1}))
+        );
+        assert_eq!(
+            publish(json!({"body": "later", "delay_ms": 60_000})).await,
+            (StatusCode::ACCEPTED, json!({"id": null}))
+        );
+        assert_eq!(
+            publish(json!({"body": "urgent", "priority": 9})).await,
+            (StatusCode::CREATED, json!({"id": 2}))
+        );
+
+        let (status, messages) =
+            request(&mailbox, Method::GET, "/queues/misc/messages?since=1", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(messages.as_array().unwrap().len(), 1);
+        assert_eq!(messages[0]["id"], 2);
+        assert_eq!(messages[0]["body"], "urgent");
+        assert_eq!(messages[0]["priority"], 9);
+        assert_eq!(messages[0]["peer_addr"], "127.0.0.1:4000");
+        assert_eq!(messages[0]["client_name"], Value::Null);
+
+        let (_, messages) = request(&mailbox, Method::GET, "/queues/misc/messages", None).await;
+        assert_eq!(messages.as_array().unwrap().len(), 2);
+        let uri = "/queues/misc/messages?since=";
+        let (_, messages) = request(&mailbox, Method::GET, uri, None).await;
+        assert_eq!(messages.as_array().unwrap().len(), 2);
+
+        assert_eq!(
+            request(&mailbox, Method::GET, "/queues", None).await,
+            (StatusCode::OK, json!([{"name": "misc", "messages": 2}]))
+        );
+    }
+
+    #[tokio::test]
+    async fn maps_errors_to_status_codes() {
+        let mailbox = Arc::new(VecDequeMailbox::new(1, EvictionPolicy::RejectNew));
+
+        let (status, body) = request(
+            &mailbox,
+            Method::POST,
+            "/queues/misc/messages",
+            Some(json!({"body": "two\nlines"})),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            body["error"],
+            "Redisish error, newline is not at the end of the string"
+        );
+        let (status, _) = request(
+            &mailbox,
+            Method::POST,
+            "/queues/two%20words/messages",
+            Some(json!({"body": "hello"})),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        let (status, body) = request(
+            &mailbox,
+            Method::POST,
+            "/queues/other/messages",
+            Some(json!({"body": "hello"})),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"], "Mailbox error, unknown channel: other");
+        let (status, _) = request(&mailbox, Method::GET, "/queues/other/messages", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let publish = Some(json!({"body": "hello"}));
+        let uri = "/queues/misc/messages";
+        let (status, _) = request(&mailbox, Method::POST, uri, publish.clone()).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (status, body) = request(&mailbox, Method::POST, uri, publish).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["error"], "Mailbox error, mailbox is full");
+    }
+
+    #[tokio::test]
+    async fn malformed_requests_get_json_errors() {
+        let mailbox = Arc::new(VecDequeMailbox::new(10, EvictionPolicy::DropOldest));
+        let stats = Arc::new(Stats::new());
+        let uri = "/queues/misc/messages";
+
+        let (status, body) = send(&mailbox, &stats, Method::POST, uri, Body::from("{")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["error"].as_str().unwrap().contains("JSON"), "{}", body);
+        let (status, body) = send(&mailbox, &stats, Method::POST, uri, Body::from("{}")).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(body["error"].as_str().unwrap().contains("body"), "{}", body);
+
+        let uri = "/queues/misc/messages?since=first";
+        let (status, body) = send(&mailbox, &stats, Method::GET, uri, Body::empty()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(
+            body["error"].as_str().unwrap().contains("query"),
+            "{}",
+            body
+        );
+    }
+
+    #[tokio::test]
+    async fn publishes_are_counted_in_the_statistics() {
+        let mailbox = Arc::new(VecDequeMailbox::new(10, EvictionPolicy::DropOldest));
+        let stats = Arc::new(Stats::new());
+        let publish = |body: Value| {
+            let body = Body::from(body.to_string());
+            send(
+                &mailbox,
+                &stats,
+                Method::POST,
+                "/queues/misc/messages",
+                body,
+            )
+        };
+        publish(json!({"body": "hello"})).await;
+        publish(json!({"body": "two\nlines"})).await;
+
+        assert_eq!(stats.commands()["PUBLISH"].count, 1);
+        assert_eq!(stats.parse_errors()["NewlineInMessage"], 1);
+    }
+
+    type Client = WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>;
+
+    async fn command(client: &mut Client, command: &str) -> String {
+        client.send(WsMessage::text(command)).await.unwrap();
+        next_text(client).await
+    }
+
+    async fn next_text(client: &mut Client) -> String {
+        match client.next().await {
+            Some(Ok(WsMessage::Text(text))) => text,
+            other => panic!("expected a text frame, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn websocket_clients_run_commands_and_receive_subscriptions() {
+        let mailbox = Arc::new(VecDequeMailbox::new(10, EvictionPolicy::DropOldest));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/ws", listener.local_addr().unwrap());
+        let (_stop, shutdown) = watch::channel(false);
+        let app = router(mailbox, Arc::new(Stats::new()), shutdown);
+        tokio::spawn(async move {
+            let app = app.into_make_service_with_connect_info::<SocketAddr>();
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        let (mut browser, _) = connect_async(&url).await.unwrap();
+        let (mut publisher, _) = connect_async(&url).await.unwrap();
+        assert_eq!(command(&mut browser, "SUBSCRIBE misc").await, "OK\n");
+        assert_eq!(command(&mut publisher, "PUBLISH hello\n").await, "OK 1\n");
+        let pushed = next_text(&mut browser).await;
+        assert!(pushed.starts_with("MESSAGE 1 "), "{}", pushed);
+        assert!(pushed.ends_with(" - misc hello\n"), "{}", pushed);
+        assert_eq!(command(&mut browser, "CHANNELS").await, "1\nmisc 1\n");
+
+        // unparsable commands close the connection like on TCP
+        publisher
+            .send(WsMessage::text("PUBLISH a\nb"))
+            .await
+            .unwrap();
+        assert!(matches!(
+            publisher.next().await,
+            Some(Ok(WsMessage::Close(None)))
+        ));
+    }
+}
@@ -0,0 +1,49 @@
+//! The redisish server as a library, so it can be embedded and tested in-process:
+//!
+//! ```no_run
+//! # async fn run() -> std::io::Result<()> {
+//! use std::sync::Arc;
+//!
+//! use tcp_server::mailbox::{EvictionPolicy, VecDequeMailbox};
+//! use tcp_server::Server;
+//!
+//! let mailbox = Arc::new(VecDequeMailbox::new(100, EvictionPolicy::DropOldest));
+//! let server = Server::builder()
+//!     .bind("127.0.0.1:0".parse().unwrap())
+//!     .mailbox(mailbox)
+//!     .start()
+//!     .await?;
+//! println!("listening on {:?}", server.local_addr());
+//! server.shutdown().await;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The tokio server needs the `async` feature. The `blocking` feature adds a server on
+//! `std::net` with a thread per connection, see [blocking].
+
+#[cfg(feature = "async")]
+pub use server::{Server, ServerBuilder, ServerHandle};
+
+#[cfg(unix)]
+pub mod activation;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+#[cfg(any(feature = "async", feature = "blocking"))]
+mod clients;
+#[cfg(any(feature = "async", feature = "blocking"))]
+mod connection;
+#[cfg(feature = "async")]
+mod gateway;
+pub mod logging;
+pub mod mailbox;
+#[cfg(feature = "async")]
+mod metrics;
+#[cfg(feature = "async")]
+mod server;
+#[cfg(any(feature = "async", feature = "blocking"))]
+mod stats;
+#[cfg(feature = "async")]
+pub mod tls;
+#[cfg(all(feature = "async", unix))]
+mod unix;
@@ -0,0 +1,48 @@
+use std::str::FromStr;
+
+use tracing_subscriber::EnvFilter;
+
+/// How log events are written to stdout
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum LogFormat {
+    /// Human readable lines
+    Text,
+    /// One JSON object per line, including the fields of the current spans
+    Json,
+}
+
+/// Installs the global subscriber, `filter` uses the `RUST_LOG` syntax, e.g. `tcp_server=debug`
+pub fn init(filter: &str, format: LogFormat) -> Result<(), String> {
+    let filter = EnvFilter::try_new(filter).map_err(|err| err.to_string())?;
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    let result = match format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().try_init(),
+    };
+    result.map_err(|err| err.to_string())
+}
+
+/// Parses `text` or `json`
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format {}, expected text or json", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_log_format() {
+        assert_eq!("text".parse(), Ok(LogFormat::Text));
+        assert_eq!("json".parse(), Ok(LogFormat::Json));
+        assert!("yaml".parse::<LogFormat>().is_err());
+    }
+}
@@ -0,0 +1,90 @@
+use super::{Data, Error, StoredMessage, VecDequeMailbox};
+
+/// Dead-letter queues keep the emails which were NACKed or timed out too often,
+/// so they do not block their channel and can be inspected and replayed later.
+impl VecDequeMailbox {
+    /// Returns the dead letters of the channel, newest first
+    pub async fn dead_letters(&self, channel: &str) -> Result<Vec<StoredMessage>, Error> {
+        let data = self.data();
+        if !data.channels.contains_key(channel) {
+            return Err(Error::UnknownChannel(channel.to_owned()));
+        }
+        Ok(data
+            .dead_letters
+            .iter()
+            .filter(|email| email.channel == channel)
+            .cloned()
+            .collect())
+    }
+
+    /// Moves the dead letters of the channel back to the channel and returns how many were moved.
+    /// The emails keep their ids, their failures are reset.
+    /// Replay stops when the mailbox is full and the policy rejects new emails.
+    pub async fn replay(&self, channel: &str) -> Result<usize, Error> {
+        let mut data = self.data();
+        if !data.channels.contains_key(channel) {
+            return Err(Error::UnknownChannel(channel.to_owned()));
+        }
+        let mut replayed = 0;
+        while let Some(position) = data
+            .dead_letters
+            .iter()
+            .rposition(|email| email.channel == channel)
+        {
+            if self.make_room(&mut data).is_err() {
+                break;
+            }
+            let email = data.dead_letters.remove(position).unwrap();
+            data.insert(email);
+            replayed += 1;
+        }
+        Ok(replayed)
+    }
+
+    /// Moves a stored email to the dead-letter queue, which is bounded by the capacity as well
+    pub(super) fn dead_letter(&self, data: &mut Data, id: u64) {
+        if let Some(email) = data.remove(id) {
+            if data.dead_letters.len() >= self.capacity {
+                data.dead_letters.pop_back();
+                data.evicted += 1;
+            }
+            data.dead_letters.push_front(email);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::mailbox::lease::tests::{fetch_body, mailbox_with_jobs};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn replay_moves_dead_letters_back() {
+        let mailbox = mailbox_with_jobs(Duration::from_secs(30)).await;
+        for _ in 0..2 {
+            let email = mailbox.fetch("jobs").await.unwrap().unwrap();
+            mailbox.nack(email.id).await.unwrap();
+        }
+        assert_eq!(mailbox.dead_letters("jobs").await.unwrap().len(), 1);
+        assert_eq!(&*mailbox.list_emails().await, "second;");
+
+        assert_eq!(mailbox.replay("jobs").await, Ok(1));
+        assert_eq!(mailbox.dead_letters("jobs").await, Ok(vec![]));
+        assert_eq!(&*mailbox.list_emails().await, "second;first;");
+        assert_eq!(fetch_body(&mailbox).await.as_deref(), Some("first"));
+    }
+
+    #[tokio::test]
+    async fn dead_letters_of_unknown_channel() {
+        let mailbox = mailbox_with_jobs(Duration::from_secs(30)).await;
+        let unknown = Err(Error::UnknownChannel("unknown".to_owned()));
+        assert_eq!(mailbox.dead_letters("unknown").await, unknown);
+        assert_eq!(
+            mailbox.replay("unknown").await,
+            Err(Error::UnknownChannel("unknown".to_owned()))
+        );
+    }
+}
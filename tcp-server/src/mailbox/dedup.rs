@@ -0,0 +1,227 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// How long an idempotency key is remembered unless configured otherwise
+pub const DEFAULT_DEDUPLICATION_WINDOW: Duration = Duration::from_secs(300);
+
+/// How many idempotency keys are remembered at most unless configured otherwise
+pub const DEFAULT_DEDUPLICATION_CAPACITY: usize = 10_000;
+
+/// Recently used idempotency keys with the id of the email they published.
+/// Keys are forgotten after the window or, if there are too many, oldest first,
+/// and when their email is evicted or its channel is deleted.
+pub(super) struct Deduplication {
+    window: Duration,
+    capacity: usize,
+    /// Email id by key, `None` while the email is scheduled
+    ids: HashMap<String, Option<u64>>,
+    /// Key by email id, for the emails which are visible
+    keys_by_id: HashMap<u64, String>,
+    /// Keys in the order they were used
+    keys: VecDeque<(Instant, String)>,
+}
+
+impl Deduplication {
+    pub(super) fn new(window: Duration, capacity: usize) -> Deduplication {
+        Deduplication {
+            window,
+            capacity,
+            ids: HashMap::new(),
+            keys_by_id: HashMap::new(),
+            keys: VecDeque::new(),
+        }
+    }
+
+    /// Returns the id published with the key, if the key was used recently
+    pub(super) fn get(&mut self, key: &str) -> Option<Option<u64>> {
+        self.forget_old(Instant::now());
+        self.ids.get(key).copied()
+    }
+
+    /// Remembers the key of a new email
+    pub(super) fn insert(&mut self, key: String, id: Option<u64>) {
+        let now = Instant::now();
+        self.forget_old(now);
+        if self.capacity == 0 {
+            return;
+        }
+        if self.keys.len() >= self.capacity {
+            if let Some((_, oldest)) = self.keys.pop_front() {
+                self.forget_key(&oldest);
+            }
+        }
+        if let Some(id) = id {
+            self.keys_by_id.insert(id, key.clone());
+        }
+        self.ids.insert(key.clone(), id);
+        self.keys.push_back((now, key));
+    }
+
+    /// Records the id of a scheduled email once it becomes visible
+    pub(super) fn update(&mut self, key: &str, id: u64) {
+        if let Some(entry) = self.ids.get_mut(key) {
+            *entry = Some(id);
+            self.keys_by_id.insert(id, key.to_owned());
+        }
+    }
+
+    /// Forgets the key of an email which was dropped before it was read,
+    /// so a retried PUBLISH stores the email again
+    pub(super) fn remove(&mut self, key: &str) {
+        if self.forget_key(key) {
+            self.keys.retain(|(_, used)| used != key);
+        }
+    }
+
+    /// Forgets the key of the visible email with the given id, see [Deduplication::remove]
+    pub(super) fn remove_id(&mut self, id: u64) {
+        if let Some(key) = self.keys_by_id.get(&id).cloned() {
+            self.remove(&key);
+        }
+    }
+
+    /// Returns whether the key was remembered
+    fn forget_key(&mut self, key: &str) -> bool {
+        match self.ids.remove(key) {
+            Some(id) => {
+                if let Some(id) = id {
+                    self.keys_by_id.remove(&id);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn forget_old(&mut self, now: Instant) {
+        while let Some((used_at, _)) = self.keys.front() {
+            if now.duration_since(*used_at) <= self.window {
+                break;
+            }
+            let (_, key) = self.keys.pop_front().unwrap();
+            self.forget_key(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use redisish::{Publish, Schedule};
+
+    use crate::mailbox::tests::sender;
+    use crate::mailbox::{EvictionPolicy, VecDequeMailbox};
+
+    use super::*;
+
+    fn publish_with_key(message: &str, key: &str) -> Publish {
+        Publish {
+            idempotency_key: Some(key.to_owned()),
+            ..message.into()
+        }
+    }
+
+    #[tokio::test]
+    async fn duplicate_returns_original_id() {
+        let mailbox = VecDequeMailbox::new(10, EvictionPolicy::DropOldest);
+        let publish = publish_with_key("hello", "k1");
+        assert_eq!(mailbox.append(&sender(), &publish).await, Ok(Some(1)));
+        assert_eq!(mailbox.append(&sender(), &publish).await, Ok(Some(1)));
+        let other = publish_with_key("hello", "k2");
+        assert_eq!(mailbox.append(&sender(), &other).await, Ok(Some(2)));
+        assert_eq!(&*mailbox.list_emails().await, "hello;hello;");
+    }
+
+    #[tokio::test]
+    async fn keys_are_forgotten_after_the_window() {
+        let mailbox = VecDequeMailbox::new(10, EvictionPolicy::DropOldest)
+            .with_deduplication(Duration::from_millis(50), 10);
+        let publish = publish_with_key("hello", "k1");
+        mailbox.append(&sender(), &publish).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(mailbox.append(&sender(), &publish).await, Ok(Some(2)));
+    }
+
+    #[tokio::test]
+    async fn oldest_keys_are_forgotten_when_cache_is_full() {
+        let mailbox = VecDequeMailbox::new(10, EvictionPolicy::DropOldest)
+            .with_deduplication(Duration::from_secs(60), 2);
+        for key in &["k1", "k2", "k3"] {
+            let publish = publish_with_key("hello", key);
+            mailbox.append(&sender(), &publish).await.unwrap();
+        }
+        let publish = publish_with_key("hello", "k3");
+        assert_eq!(mailbox.append(&sender(), &publish).await, Ok(Some(3)));
+        let publish = publish_with_key("hello", "k1");
+        assert_eq!(mailbox.append(&sender(), &publish).await, Ok(Some(4)));
+    }
+
+    #[tokio::test]
+    async fn keys_of_evicted_emails_are_forgotten() {
+        let mailbox = VecDequeMailbox::new(1, EvictionPolicy::DropOldest);
+        let publish = publish_with_key("hello", "k1");
+        mailbox.append(&sender(), &publish).await.unwrap();
+        mailbox.append(&sender(), &"other".into()).await.unwrap();
+        assert_eq!(mailbox.append(&sender(), &publish).await, Ok(Some(3)));
+        assert_eq!(&*mailbox.list_emails().await, "hello;");
+    }
+
+    #[tokio::test]
+    async fn keys_of_evicted_scheduled_emails_are_forgotten() {
+        let mailbox = VecDequeMailbox::new(1, EvictionPolicy::DropOldest);
+        let publish = Publish {
+            schedule: Some(Schedule::Delay(Duration::from_secs(60))),
+            ..publish_with_key("later", "k1")
+        };
+        assert_eq!(mailbox.append(&sender(), &publish).await, Ok(None));
+        mailbox.append(&sender(), &"now".into()).await.unwrap();
+        let publish = publish_with_key("hello", "k1");
+        assert_eq!(mailbox.append(&sender(), &publish).await, Ok(Some(2)));
+    }
+
+    #[tokio::test]
+    async fn keys_of_deleted_channels_are_forgotten() {
+        let mailbox = VecDequeMailbox::new(10, EvictionPolicy::DropOldest);
+        mailbox.create_channel("general").await.unwrap();
+        let publish = Publish {
+            channel: Some("general".to_owned()),
+            ..publish_with_key("hello", "k1")
+        };
+        let scheduled = Publish {
+            channel: Some("general".to_owned()),
+            schedule: Some(Schedule::Delay(Duration::from_secs(60))),
+            ..publish_with_key("later", "k2")
+        };
+        mailbox.append(&sender(), &publish).await.unwrap();
+        mailbox.append(&sender(), &scheduled).await.unwrap();
+
+        mailbox.delete_channel("general").await.unwrap();
+        mailbox.create_channel("general").await.unwrap();
+        assert_eq!(mailbox.append(&sender(), &publish).await, Ok(Some(2)));
+        let scheduled = Publish {
+            schedule: None,
+            ..scheduled
+        };
+        assert_eq!(mailbox.append(&sender(), &scheduled).await, Ok(Some(3)));
+    }
+
+    #[test]
+    fn removed_key_is_not_forgotten_again_when_reused() {
+        let mut deduplication = Deduplication::new(Duration::from_secs(60), 2);
+        deduplication.insert("k1".to_owned(), Some(1));
+        deduplication.remove_id(1);
+        assert_eq!(deduplication.get("k1"), None);
+        deduplication.insert("k1".to_owned(), Some(2));
+        deduplication.insert("k2".to_owned(), Some(3));
+        assert_eq!(deduplication.get("k1"), Some(Some(2)));
+    }
+
+    #[test]
+    fn scheduled_email_gets_its_id_later() {
+        let mut deduplication = Deduplication::new(Duration::from_secs(60), 10);
+        deduplication.insert("k1".to_owned(), None);
+        assert_eq!(deduplication.get("k1"), Some(None));
+        deduplication.update("k1", 7);
+        assert_eq!(deduplication.get("k1"), Some(Some(7)));
+        assert_eq!(deduplication.get("k2"), None);
+    }
+}
@@ -0,0 +1,103 @@
+use std::time::SystemTime;
+
+use super::{Data, VecDequeMailbox};
+
+/// Per-email time to live: emails published with `EX <seconds>` are dropped once it runs out.
+/// Reads never see expired emails, the sweeper frees their memory in the meantime.
+impl VecDequeMailbox {
+    /// Drops all expired emails and returns how many were dropped
+    pub async fn sweep(&self) -> usize {
+        let mut data = self.data();
+        let expired = data.expired;
+        self.refresh(&mut data);
+        (data.expired - expired) as usize
+    }
+}
+
+impl Data {
+    /// Drops the emails which expired at the given time
+    pub(super) fn remove_expired(&mut self, now: SystemTime) {
+        while let Some(&(expires_at, id)) = self.expiring.iter().next() {
+            if expires_at > now {
+                break;
+            }
+            self.expiring.remove(&(expires_at, id));
+            self.remove(id);
+            self.expired += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use redisish::Publish;
+
+    use crate::mailbox::tests::sender;
+    use crate::mailbox::{Error, EvictionPolicy};
+
+    use super::*;
+
+    fn publish_with_ttl(message: &str, expire: Duration) -> Publish {
+        Publish {
+            expire: Some(expire),
+            ..message.into()
+        }
+    }
+
+    #[tokio::test]
+    async fn expired_emails_are_not_retrieved() {
+        let mailbox = VecDequeMailbox::new(10, EvictionPolicy::DropOldest);
+        let short_lived = publish_with_ttl("short", Duration::from_millis(50));
+        mailbox.append(&sender(), &short_lived).await.unwrap();
+        let long_lived = publish_with_ttl("long", Duration::from_secs(60));
+        mailbox.append(&sender(), &long_lived).await.unwrap();
+        mailbox.append(&sender(), &"forever".into()).await.unwrap();
+        assert_eq!(&*mailbox.list_emails().await, "forever;long;short;");
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(&*mailbox.list_emails().await, "forever;long;");
+        assert_eq!(mailbox.list_channels().await[0], ("misc".to_owned(), 2));
+        assert_eq!(mailbox.expired().await, 1);
+        assert_eq!(mailbox.evicted().await, 0);
+    }
+
+    #[tokio::test]
+    async fn sweep_drops_expired_emails() {
+        let mailbox = VecDequeMailbox::new(10, EvictionPolicy::DropOldest);
+        for message in &["a", "b"] {
+            let publish = publish_with_ttl(message, Duration::from_millis(50));
+            mailbox.append(&sender(), &publish).await.unwrap();
+        }
+        assert_eq!(mailbox.sweep().await, 0);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(mailbox.sweep().await, 2);
+        assert_eq!(mailbox.list_messages().await, vec![]);
+    }
+
+    #[tokio::test]
+    async fn expired_emails_cannot_be_fetched() {
+        let mailbox = VecDequeMailbox::new(10, EvictionPolicy::DropOldest);
+        let publish = publish_with_ttl("job", Duration::from_millis(50));
+        mailbox.append(&sender(), &publish).await.unwrap();
+        let email = mailbox.fetch("misc").await.unwrap().unwrap();
+        assert!(email.expires_at.is_some());
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        mailbox.sweep().await;
+        assert_eq!(mailbox.fetch("misc").await, Ok(None));
+    }
+
+    #[tokio::test]
+    async fn time_to_live_beyond_the_representable_time_is_rejected() {
+        let mailbox = VecDequeMailbox::new(10, EvictionPolicy::DropOldest);
+        let publish = publish_with_ttl("boom", Duration::from_secs(u64::MAX));
+        assert_eq!(
+            mailbox.append(&sender(), &publish).await,
+            Err(Error::TimeOutOfRange)
+        );
+        mailbox.append(&sender(), &"fine".into()).await.unwrap();
+        assert_eq!(&*mailbox.list_emails().await, "fine;");
+    }
+}
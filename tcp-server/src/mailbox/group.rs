@@ -0,0 +1,164 @@
+use std::time::Instant;
+
+use super::{Error, StoredMessage, VecDequeMailbox};
+
+/// How many committed offsets of consumer groups are kept at most unless configured otherwise
+pub const DEFAULT_MAX_GROUPS: usize = 1_000;
+
+/// Committed offset of a consumer group in a channel
+pub(super) struct Offset {
+    id: u64,
+    /// Last READGROUP or COMMIT of the group in the channel
+    used_at: Instant,
+}
+
+/// Consumer groups: every group has its own committed offset per channel,
+/// so independent readers each see every email once.
+/// Groups are created on first use and start before the oldest email.
+/// Offsets are email ids, so groups read in publish order regardless of the priority.
+/// If there are too many offsets, the least recently used one is forgotten,
+/// so abandoned groups do not pile up.
+impl VecDequeMailbox {
+    /// Returns up to `count` emails of the channel after the offset committed by the group,
+    /// oldest first
+    pub async fn read_group(
+        &self,
+        group: &str,
+        channel: &str,
+        count: Option<usize>,
+    ) -> Result<Vec<StoredMessage>, Error> {
+        let mut data = self.data();
+        if !data.channels.contains_key(channel) {
+            return Err(Error::UnknownChannel(channel.to_owned()));
+        }
+        self.refresh(&mut data);
+        let offset = match data
+            .offsets
+            .get_mut(&(channel.to_owned(), group.to_owned()))
+        {
+            Some(offset) => {
+                offset.used_at = Instant::now();
+                offset.id
+            }
+            None => 0,
+        };
+        Ok(data
+            .emails
+            .iter()
+            .rev()
+            .filter(|email| email.channel == channel && email.id > offset)
+            .take(count.unwrap_or(usize::MAX))
+            .cloned()
+            .collect())
+    }
+
+    /// Marks the emails of the channel up to the id as read by the group.
+    /// Committing a lower id rewinds the group.
+    pub async fn commit(&self, group: &str, channel: &str, id: u64) -> Result<(), Error> {
+        let mut data = self.data();
+        if !data.channels.contains_key(channel) {
+            return Err(Error::UnknownChannel(channel.to_owned()));
+        }
+        if id >= data.next_id {
+            return Err(Error::UnknownEmail(id));
+        }
+        let key = (channel.to_owned(), group.to_owned());
+        if !data.offsets.contains_key(&key) && data.offsets.len() >= self.max_groups {
+            let least_recently_used = data
+                .offsets
+                .iter()
+                .min_by_key(|(_, offset)| offset.used_at)
+                .map(|(key, _)| key.clone());
+            if let Some(key) = least_recently_used {
+                data.offsets.remove(&key);
+            }
+        }
+        let used_at = Instant::now();
+        data.offsets.insert(key, Offset { id, used_at });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mailbox::tests::{publish_to, sender};
+    use crate::mailbox::EvictionPolicy;
+
+    use super::*;
+
+    async fn bodies(mailbox: &VecDequeMailbox, group: &str, count: Option<usize>) -> Vec<String> {
+        let emails = mailbox.read_group(group, "general", count).await.unwrap();
+        emails.into_iter().map(|email| email.body).collect()
+    }
+
+    #[tokio::test]
+    async fn groups_have_independent_offsets() {
+        let mailbox = VecDequeMailbox::new(10, EvictionPolicy::DropOldest);
+        mailbox.create_channel("general").await.unwrap();
+        for message in &["a", "b", "c"] {
+            let publish = publish_to("general", message);
+            mailbox.append(&sender(), &publish).await.unwrap();
+        }
+        mailbox.append(&sender(), &"misc".into()).await.unwrap();
+
+        assert_eq!(bodies(&mailbox, "tui", Some(2)).await, vec!["a", "b"]);
+        assert_eq!(mailbox.commit("tui", "general", 2).await, Ok(()));
+        assert_eq!(bodies(&mailbox, "tui", None).await, vec!["c"]);
+        assert_eq!(
+            bodies(&mailbox, "archiver", None).await,
+            vec!["a", "b", "c"]
+        );
+
+        assert_eq!(mailbox.commit("tui", "general", 0).await, Ok(()));
+        assert_eq!(bodies(&mailbox, "tui", None).await, vec!["a", "b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn commit_validates_channel_and_id() {
+        let mailbox = VecDequeMailbox::new(10, EvictionPolicy::DropOldest);
+        mailbox.append(&sender(), &"a".into()).await.unwrap();
+        assert_eq!(
+            mailbox.commit("tui", "general", 1).await,
+            Err(Error::UnknownChannel("general".to_owned()))
+        );
+        assert_eq!(
+            mailbox.commit("tui", "misc", 2).await,
+            Err(Error::UnknownEmail(2))
+        );
+    }
+
+    #[tokio::test]
+    async fn least_recently_used_offset_is_forgotten_when_there_are_too_many() {
+        let mailbox = VecDequeMailbox::new(10, EvictionPolicy::DropOldest).with_max_groups(2);
+        mailbox.create_channel("general").await.unwrap();
+        for message in &["a", "b"] {
+            let publish = publish_to("general", message);
+            mailbox.append(&sender(), &publish).await.unwrap();
+        }
+        mailbox.commit("tui", "general", 1).await.unwrap();
+        mailbox.commit("archiver", "general", 1).await.unwrap();
+        assert_eq!(bodies(&mailbox, "tui", None).await, vec!["b"]);
+
+        mailbox.commit("indexer", "general", 2).await.unwrap();
+        assert_eq!(bodies(&mailbox, "tui", None).await, vec!["b"]);
+        assert_eq!(
+            bodies(&mailbox, "indexer", None).await,
+            Vec::<String>::new()
+        );
+        assert_eq!(bodies(&mailbox, "archiver", None).await, vec!["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn deleting_channel_resets_offsets() {
+        let mailbox = VecDequeMailbox::new(10, EvictionPolicy::DropOldest);
+        mailbox.create_channel("general").await.unwrap();
+        let publish = publish_to("general", "a");
+        mailbox.append(&sender(), &publish).await.unwrap();
+        mailbox.commit("tui", "general", 1).await.unwrap();
+
+        mailbox.delete_channel("general").await.unwrap();
+        mailbox.create_channel("general").await.unwrap();
+        mailbox.append(&sender(), &publish).await.unwrap();
+        assert_eq!(bodies(&mailbox, "tui", None).await, vec!["a"]);
+    }
+}
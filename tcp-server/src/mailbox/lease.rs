@@ -0,0 +1,192 @@
+use std::cmp::Reverse;
+use std::time::Instant;
+
+use super::{Data, Error, StoredMessage, VecDequeMailbox};
+
+/// At-least-once delivery: FETCH leases an email for the visibility timeout,
+/// ACK removes it from the mailbox and NACK makes it available again.
+/// An email which is not acknowledged in time is delivered again,
+/// unless it failed too often and moved to the dead-letter queue.
+impl VecDequeMailbox {
+    /// Leases the email of the channel with the highest priority which is not leased
+    /// by another consumer, the oldest one if several have the same priority
+    pub async fn fetch(&self, channel: &str) -> Result<Option<StoredMessage>, Error> {
+        let mut data = self.data();
+        if !data.channels.contains_key(channel) {
+            return Err(Error::UnknownChannel(channel.to_owned()));
+        }
+        self.refresh(&mut data);
+        self.expire_leases(&mut data);
+        let leases = &data.leases;
+        let email = data
+            .emails
+            .iter()
+            .filter(|email| email.channel == channel)
+            .filter(|email| !leases.contains_key(&email.id))
+            .max_by_key(|email| (email.priority, Reverse(email.id)))
+            .cloned();
+        if let Some(email) = &email {
+            let until = Instant::now()
+                .checked_add(self.visibility_timeout)
+                .ok_or(Error::TimeOutOfRange)?;
+            data.leases.insert(email.id, until);
+        }
+        Ok(email)
+    }
+
+    /// Removes a leased email from the mailbox
+    pub async fn ack(&self, id: u64) -> Result<(), Error> {
+        let mut data = self.data();
+        self.expire_leases(&mut data);
+        data.leases.remove(&id).ok_or(Error::NotLeased(id))?;
+        data.remove(id);
+        Ok(())
+    }
+
+    /// Returns a leased email to the channel, so it can be fetched again right away
+    pub async fn nack(&self, id: u64) -> Result<(), Error> {
+        let mut data = self.data();
+        self.expire_leases(&mut data);
+        data.leases.remove(&id).ok_or(Error::NotLeased(id))?;
+        self.fail(&mut data, id);
+        Ok(())
+    }
+
+    /// Releases leases which timed out, which counts as a failed delivery
+    fn expire_leases(&self, data: &mut Data) {
+        let now = Instant::now();
+        let expired: Vec<u64> = data
+            .leases
+            .iter()
+            .filter(|(_, until)| **until <= now)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in expired {
+            data.leases.remove(&id);
+            self.fail(data, id);
+        }
+    }
+
+    /// Counts a failed delivery and moves the email to the dead-letter queue if it failed too often
+    fn fail(&self, data: &mut Data, id: u64) {
+        let failures = data.failures.entry(id).or_default();
+        *failures += 1;
+        if *failures > self.max_failures {
+            self.dead_letter(data, id);
+        }
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use std::time::Duration;
+
+    use redisish::Publish;
+
+    use crate::mailbox::tests::{publish_to, sender};
+    use crate::mailbox::EvictionPolicy;
+
+    use super::*;
+
+    pub(in crate::mailbox) async fn mailbox_with_jobs(
+        visibility_timeout: Duration,
+    ) -> VecDequeMailbox {
+        let mailbox = VecDequeMailbox::new(10, EvictionPolicy::DropOldest)
+            .with_visibility_timeout(visibility_timeout)
+            .with_max_failures(1);
+        mailbox.create_channel("jobs").await.unwrap();
+        for job in &["first", "second"] {
+            let publish = publish_to("jobs", job);
+            mailbox.append(&sender(), &publish).await.unwrap();
+        }
+        mailbox
+    }
+
+    pub(in crate::mailbox) async fn fetch_body(mailbox: &VecDequeMailbox) -> Option<String> {
+        mailbox.fetch("jobs").await.unwrap().map(|email| email.body)
+    }
+
+    #[tokio::test]
+    async fn fetch_leases_oldest_email() {
+        let mailbox = mailbox_with_jobs(Duration::from_secs(30)).await;
+        assert_eq!(fetch_body(&mailbox).await.as_deref(), Some("first"));
+        assert_eq!(fetch_body(&mailbox).await.as_deref(), Some("second"));
+        assert_eq!(fetch_body(&mailbox).await, None);
+        assert_eq!(
+            mailbox.fetch("unknown").await,
+            Err(Error::UnknownChannel("unknown".to_owned()))
+        );
+    }
+
+    #[tokio::test]
+    async fn visibility_timeout_beyond_the_representable_time_is_rejected() {
+        let mailbox = mailbox_with_jobs(Duration::MAX).await;
+        assert_eq!(mailbox.fetch("jobs").await, Err(Error::TimeOutOfRange));
+        assert_eq!(
+            fetch_body(&mailbox.with_visibility_timeout(Duration::from_secs(30)))
+                .await
+                .as_deref(),
+            Some("first")
+        );
+    }
+
+    #[tokio::test]
+    async fn fetch_leases_higher_priority_first() {
+        let mailbox = mailbox_with_jobs(Duration::from_secs(30)).await;
+        let urgent = Publish {
+            priority: 1,
+            ..publish_to("jobs", "urgent")
+        };
+        mailbox.append(&sender(), &urgent).await.unwrap();
+        assert_eq!(fetch_body(&mailbox).await.as_deref(), Some("urgent"));
+        assert_eq!(fetch_body(&mailbox).await.as_deref(), Some("first"));
+    }
+
+    #[tokio::test]
+    async fn ack_removes_email() {
+        let mailbox = mailbox_with_jobs(Duration::from_secs(30)).await;
+        let email = mailbox.fetch("jobs").await.unwrap().unwrap();
+        assert_eq!(mailbox.ack(email.id).await, Ok(()));
+        assert_eq!(mailbox.ack(email.id).await, Err(Error::NotLeased(email.id)));
+        assert_eq!(&*mailbox.list_emails().await, "second;");
+        assert_eq!(mailbox.list_channels().await[0], ("jobs".to_owned(), 1));
+    }
+
+    #[tokio::test]
+    async fn nack_makes_email_available_again() {
+        let mailbox = mailbox_with_jobs(Duration::from_secs(30)).await;
+        let email = mailbox.fetch("jobs").await.unwrap().unwrap();
+        assert_eq!(mailbox.nack(email.id).await, Ok(()));
+        assert_eq!(fetch_body(&mailbox).await.as_deref(), Some("first"));
+    }
+
+    #[tokio::test]
+    async fn expired_lease_is_delivered_again() {
+        let mailbox = mailbox_with_jobs(Duration::from_millis(50)).await;
+        let email = mailbox.fetch("jobs").await.unwrap().unwrap();
+        assert_eq!(fetch_body(&mailbox).await.as_deref(), Some("second"));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(mailbox.ack(email.id).await, Err(Error::NotLeased(email.id)));
+        assert_eq!(fetch_body(&mailbox).await.as_deref(), Some("first"));
+    }
+
+    #[tokio::test]
+    async fn repeated_failures_move_email_to_dead_letters() {
+        let mailbox = mailbox_with_jobs(Duration::from_millis(50)).await;
+        let email = mailbox.fetch("jobs").await.unwrap().unwrap();
+        mailbox.nack(email.id).await.unwrap();
+        assert_eq!(mailbox.dead_letters("jobs").await, Ok(vec![]));
+
+        // second failure is a timeout
+        let email = mailbox.fetch("jobs").await.unwrap().unwrap();
+        assert_eq!(email.body, "first");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(fetch_body(&mailbox).await.as_deref(), Some("second"));
+
+        let dead_letters = mailbox.dead_letters("jobs").await.unwrap();
+        assert_eq!(dead_letters.len(), 1);
+        assert_eq!(dead_letters[0].id, email.id);
+        assert_eq!(&*mailbox.list_emails().await, "second;");
+        assert_eq!(mailbox.list_channels().await[0], ("jobs".to_owned(), 1));
+    }
+}
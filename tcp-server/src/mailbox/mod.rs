@@ -0,0 +1,809 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use redisish::Publish;
+
+use dedup::Deduplication;
+use group::Offset;
+use schedule::Scheduled;
+use search::SearchIndex;
+use snapshot::Snapshots;
+use subscribe::Subscribers;
+
+pub use dedup::{DEFAULT_DEDUPLICATION_CAPACITY, DEFAULT_DEDUPLICATION_WINDOW};
+pub use group::DEFAULT_MAX_GROUPS;
+pub use subscribe::{
+    SlowSubscriberPolicy, Subscriber, DEFAULT_BACKPRESSURE_TIMEOUT, DEFAULT_SUBSCRIBER_BUFFER,
+};
+
+mod dead_letter;
+mod dedup;
+mod expire;
+mod group;
+mod lease;
+mod schedule;
+mod search;
+mod snapshot;
+mod subscribe;
+mod timer;
+
+/// Channel for messages published without a channel, it always exists
+pub const DEFAULT_CHANNEL: &str = "misc";
+
+/// How long a fetched message stays invisible to other consumers unless configured otherwise
+pub const DEFAULT_VISIBILITY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often an email may be NACKed or time out before it moves to the dead-letter queue
+/// unless configured otherwise
+pub const DEFAULT_MAX_FAILURES: u32 = 5;
+
+/// Longest configurable duration in seconds, about 100 years,
+/// so the current time plus the duration is always representable
+pub const MAX_DURATION_SECS: u64 = 100 * 365 * 24 * 60 * 60;
+
+/// Decides what happens when the mailbox reaches its capacity
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum EvictionPolicy {
+    /// Ring buffer: the oldest email is dropped to make space for the new one
+    DropOldest,
+    /// New emails are rejected with an error until space is available
+    RejectNew,
+    /// Emails older than the given age are dropped.
+    /// If the mailbox is still full afterwards, the oldest email is dropped.
+    MaxAge(Duration),
+}
+
+/// Mailbox error
+#[derive(Eq, PartialEq, Debug)]
+pub enum Error {
+    Full,
+    UnknownChannel(String),
+    ChannelExists(String),
+    DefaultChannel,
+    NotLeased(u64),
+    UnknownEmail(u64),
+    InvalidQuery(String),
+    /// The time to live or delay reaches beyond the representable time
+    TimeOutOfRange,
+}
+
+/// Address of a connected client
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum PeerAddr {
+    Tcp(SocketAddr),
+    /// Clients of the Unix domain socket listener are unnamed
+    Unix,
+}
+
+/// The client which published a message
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Sender {
+    pub peer_addr: PeerAddr,
+    pub client_name: Option<String>,
+}
+
+/// An email together with the metadata assigned by the server when it was received
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct StoredMessage {
+    pub id: u64,
+    pub received_at: SystemTime,
+    pub peer_addr: PeerAddr,
+    pub client_name: Option<String>,
+    pub channel: String,
+    pub body: String,
+    /// Time after which the email is dropped, published with `EX <seconds>`
+    pub expires_at: Option<SystemTime>,
+    pub priority: u8,
+}
+
+/// Sorts emails for ordered reads: higher priority first, oldest first within the same priority
+pub fn sort_by_priority(emails: &mut [StoredMessage]) {
+    emails.sort_by_key(|email| (Reverse(email.priority), email.id));
+}
+
+struct Data {
+    emails: VecDeque<StoredMessage>,
+    /// Number of stored emails per channel
+    channels: BTreeMap<String, u64>,
+    /// Time until which a fetched email is invisible to other consumers, by email id
+    leases: HashMap<u64, Instant>,
+    /// Number of NACKs and lease timeouts, by email id
+    failures: HashMap<u64, u32>,
+    /// Emails which failed too often, newest first
+    dead_letters: VecDeque<StoredMessage>,
+    /// Committed offsets of consumer groups by channel and group name
+    offsets: HashMap<(String, String), Offset>,
+    /// Emails which are not visible yet, by the time they become visible and sequence number
+    scheduled: BTreeMap<(SystemTime, u64), Scheduled>,
+    next_sequence: u64,
+    /// Emails published with a time to live, by expiry time and id
+    expiring: BTreeSet<(SystemTime, u64)>,
+    /// Recently used idempotency keys of PUBLISH
+    deduplication: Deduplication,
+    search_index: SearchIndex,
+    subscribers: Subscribers,
+    next_id: u64,
+    /// Size of the stored email bodies
+    bytes: usize,
+    evicted: u64,
+    expired: u64,
+    /// Shared with [VecDequeMailbox], invalidated whenever the emails change
+    snapshots: Arc<Snapshots>,
+}
+
+impl Data {
+    /// Number of stored emails, including the scheduled ones
+    fn len(&self) -> usize {
+        self.emails.len() + self.scheduled.len()
+    }
+
+    /// Drops the oldest visible email or, if there is none, the email scheduled last
+    fn evict_oldest(&mut self) {
+        if let Some(oldest) = self.emails.pop_back() {
+            self.forget(&oldest);
+            self.deduplication.remove_id(oldest.id);
+            self.evicted += 1;
+        } else if let Some((_, scheduled)) = self.scheduled.pop_last() {
+            if let Some(key) = &scheduled.idempotency_key {
+                self.deduplication.remove(key);
+            }
+            self.evicted += 1;
+        }
+    }
+
+    /// Removes the email with the given id, if it is still stored
+    fn remove(&mut self, id: u64) -> Option<StoredMessage> {
+        let position = self.emails.iter().position(|email| email.id == id)?;
+        let email = self.emails.remove(position)?;
+        self.forget(&email);
+        Some(email)
+    }
+
+    /// Returns the stored email with the given id
+    fn get(&self, id: u64) -> Option<&StoredMessage> {
+        let position = self.emails.partition_point(|stored| stored.id > id);
+        self.emails.get(position).filter(|email| email.id == id)
+    }
+
+    /// Updates the bookkeeping after an email was removed
+    fn forget(&mut self, email: &StoredMessage) {
+        self.snapshots.invalidate();
+        self.bytes -= email.body.len();
+        self.search_index.remove(email);
+        if let Some(count) = self.channels.get_mut(&email.channel) {
+            *count -= 1;
+        }
+        self.leases.remove(&email.id);
+        self.failures.remove(&email.id);
+        if let Some(expires_at) = email.expires_at {
+            self.expiring.remove(&(expires_at, email.id));
+        }
+    }
+
+    /// Stores an email keeping the emails sorted by id, newest first
+    fn insert(&mut self, email: StoredMessage) {
+        self.snapshots.invalidate();
+        *self.channels.entry(email.channel.clone()).or_default() += 1;
+        self.bytes += email.body.len();
+        self.search_index.insert(&email);
+        if let Some(expires_at) = email.expires_at {
+            self.expiring.insert((expires_at, email.id));
+        }
+        let position = self.emails.partition_point(|stored| stored.id > email.id);
+        self.emails.insert(position, email);
+    }
+}
+
+pub struct VecDequeMailbox {
+    data: Mutex<Data>,
+    snapshots: Arc<Snapshots>,
+    capacity: usize,
+    policy: EvictionPolicy,
+    visibility_timeout: Duration,
+    max_failures: u32,
+    max_groups: usize,
+}
+
+impl VecDequeMailbox {
+    /// Appends an email to an existing channel and returns its id,
+    /// evicting older emails according to the [EvictionPolicy]
+    /// or returning [Error::Full] if the policy is [EvictionPolicy::RejectNew].
+    ///
+    /// A scheduled email gets its id when it becomes visible, so `None` is returned for it.
+    /// If the idempotency key was used recently, nothing is stored and the original id is returned.
+    pub async fn append(&self, sender: &Sender, publish: &Publish) -> Result<Option<u64>, Error> {
+        self.append_excluding(None, sender, publish).await
+    }
+
+    /// Like [VecDequeMailbox::append], but does not wait for the subscriber of the publishing
+    /// connection, which cannot take emails while its connection waits for the reply
+    pub async fn append_from(
+        &self,
+        subscriber: &Subscriber,
+        sender: &Sender,
+        publish: &Publish,
+    ) -> Result<Option<u64>, Error> {
+        self.append_excluding(Some(subscriber), sender, publish)
+            .await
+    }
+
+    async fn append_excluding(
+        &self,
+        own: Option<&Subscriber>,
+        sender: &Sender,
+        publish: &Publish,
+    ) -> Result<Option<u64>, Error> {
+        let channel = publish.channel.as_deref().unwrap_or(DEFAULT_CHANNEL);
+        self.wait_for_subscribers(channel, own).await;
+        let mut data = self.data();
+        if let Some(key) = &publish.idempotency_key {
+            if let Some(id) = data.deduplication.get(key) {
+                return Ok(id);
+            }
+        }
+        if !data.channels.contains_key(channel) {
+            return Err(Error::UnknownChannel(channel.to_owned()));
+        }
+        let now = SystemTime::now();
+        let expires_at = publish
+            .expire
+            .map(|expire| now.checked_add(expire).ok_or(Error::TimeOutOfRange))
+            .transpose()?;
+        let visible_at = publish
+            .schedule
+            .map(|schedule| schedule::visible_at(schedule, now))
+            .transpose()?;
+        self.make_room(&mut data)?;
+        let scheduled = Scheduled {
+            sender: sender.clone(),
+            channel: channel.to_owned(),
+            body: publish.message.clone(),
+            expires_at,
+            priority: publish.priority,
+            idempotency_key: publish.idempotency_key.clone(),
+        };
+        let id = match visible_at {
+            Some(visible_at) if visible_at > now => {
+                data.schedule(visible_at, scheduled);
+                None
+            }
+            _ => Some(data.publish(scheduled, now)),
+        };
+        if let Some(key) = &publish.idempotency_key {
+            data.deduplication.insert(key.clone(), id);
+        }
+        Ok(id)
+    }
+
+    /// Creates an empty channel
+    pub async fn create_channel(&self, channel: &str) -> Result<(), Error> {
+        let mut data = self.data();
+        if data.channels.contains_key(channel) {
+            return Err(Error::ChannelExists(channel.to_owned()));
+        }
+        data.channels.insert(channel.to_owned(), 0);
+        Ok(())
+    }
+
+    /// Deletes a channel together with its emails
+    pub async fn delete_channel(&self, channel: &str) -> Result<(), Error> {
+        if channel == DEFAULT_CHANNEL {
+            return Err(Error::DefaultChannel);
+        }
+        let mut data = self.data();
+        if data.channels.remove(channel).is_none() {
+            return Err(Error::UnknownChannel(channel.to_owned()));
+        }
+        let (removed, kept) = std::mem::take(&mut data.emails)
+            .into_iter()
+            .partition::<Vec<_>, _>(|email| email.channel == channel);
+        data.emails = kept.into();
+        for email in &removed {
+            data.forget(email);
+            data.deduplication.remove_id(email.id);
+        }
+        data.dead_letters.retain(|email| email.channel != channel);
+        let (removed, kept) = std::mem::take(&mut data.scheduled)
+            .into_iter()
+            .partition::<BTreeMap<_, _>, _>(|(_, email)| email.channel == channel);
+        data.scheduled = kept;
+        for key in removed
+            .values()
+            .filter_map(|email| email.idempotency_key.as_ref())
+        {
+            data.deduplication.remove(key);
+        }
+        data.subscribers.remove_channel(channel);
+        data.offsets
+            .retain(|(offset_channel, _), _| offset_channel != channel);
+        Ok(())
+    }
+
+    /// Returns channel names with the number of stored emails, sorted by name
+    pub async fn list_channels(&self) -> Vec<(String, u64)> {
+        let mut data = self.data();
+        self.refresh(&mut data);
+        data.channels
+            .iter()
+            .map(|(channel, count)| (channel.clone(), *count))
+            .collect()
+    }
+
+    /// Returns all emails with their metadata, newest first
+    pub async fn list_messages(&self) -> Vec<StoredMessage> {
+        let mut data = self.data();
+        self.refresh(&mut data);
+        data.emails.iter().cloned().collect()
+    }
+
+    /// Returns the emails with an id greater than `since` with their metadata, newest first
+    pub async fn list_messages_since(&self, since: u64) -> Vec<StoredMessage> {
+        let mut data = self.data();
+        self.refresh(&mut data);
+        let newer = data.emails.partition_point(|email| email.id > since);
+        data.emails.range(..newer).cloned().collect()
+    }
+
+    /// Returns the emails of the channel with an id greater than `since`, oldest first
+    pub async fn messages_since(
+        &self,
+        channel: &str,
+        since: u64,
+    ) -> Result<Vec<StoredMessage>, Error> {
+        let mut data = self.data();
+        if !data.channels.contains_key(channel) {
+            return Err(Error::UnknownChannel(channel.to_owned()));
+        }
+        self.refresh(&mut data);
+        let newer = data.emails.partition_point(|email| email.id > since);
+        Ok(data
+            .emails
+            .range(..newer)
+            .rev()
+            .filter(|email| email.channel == channel)
+            .cloned()
+            .collect())
+    }
+
+    /// Number of emails dropped by the [EvictionPolicy] since the start
+    pub async fn evicted(&self) -> u64 {
+        self.data().evicted
+    }
+
+    /// Number of bytes in the bodies of the stored emails
+    pub async fn stored_bytes(&self) -> usize {
+        let mut data = self.data();
+        self.refresh(&mut data);
+        data.bytes
+    }
+
+    /// Number of emails dropped because their time to live ran out since the start
+    pub async fn expired(&self) -> u64 {
+        self.data().expired
+    }
+
+    /// Makes space for one more email according to the [EvictionPolicy]
+    fn make_room(&self, data: &mut Data) -> Result<(), Error> {
+        self.refresh(data);
+        if data.len() >= self.capacity {
+            match self.policy {
+                EvictionPolicy::RejectNew => return Err(Error::Full),
+                EvictionPolicy::DropOldest | EvictionPolicy::MaxAge(_) => data.evict_oldest(),
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops emails older than the max age, newest emails are at the front
+    fn evict_too_old(&self, data: &mut Data) {
+        if let EvictionPolicy::MaxAge(max_age) = self.policy {
+            while let Some(oldest) = data.emails.back() {
+                if oldest.received_at.elapsed().unwrap_or_default() <= max_age {
+                    break;
+                }
+                data.evict_oldest();
+            }
+        }
+    }
+}
+
+impl VecDequeMailbox {
+    // TODO how to return Box<dyn Mailbox>?
+    pub fn new(capacity: usize, policy: EvictionPolicy) -> VecDequeMailbox {
+        let snapshots = Arc::new(Snapshots::default());
+        VecDequeMailbox {
+            data: Mutex::new(Data {
+                emails: VecDeque::new(),
+                channels: BTreeMap::from([(DEFAULT_CHANNEL.to_owned(), 0)]),
+                leases: HashMap::new(),
+                failures: HashMap::new(),
+                dead_letters: VecDeque::new(),
+                offsets: HashMap::new(),
+                scheduled: BTreeMap::new(),
+                next_sequence: 0,
+                expiring: BTreeSet::new(),
+                deduplication: Deduplication::new(
+                    DEFAULT_DEDUPLICATION_WINDOW,
+                    DEFAULT_DEDUPLICATION_CAPACITY,
+                ),
+                search_index: SearchIndex::default(),
+                subscribers: Subscribers::new(
+                    DEFAULT_SUBSCRIBER_BUFFER,
+                    SlowSubscriberPolicy::DropOldest,
+                ),
+                next_id: 1,
+                bytes: 0,
+                evicted: 0,
+                expired: 0,
+                snapshots: snapshots.clone(),
+            }),
+            snapshots,
+            capacity,
+            policy,
+            visibility_timeout: DEFAULT_VISIBILITY_TIMEOUT,
+            max_failures: DEFAULT_MAX_FAILURES,
+            max_groups: DEFAULT_MAX_GROUPS,
+        }
+    }
+
+    /// Sets how long a fetched email stays invisible to other consumers
+    pub fn with_visibility_timeout(mut self, visibility_timeout: Duration) -> VecDequeMailbox {
+        self.visibility_timeout = visibility_timeout;
+        self
+    }
+
+    /// Sets how often an email may be NACKed or time out before it is dead-lettered
+    pub fn with_max_failures(mut self, max_failures: u32) -> VecDequeMailbox {
+        self.max_failures = max_failures;
+        self
+    }
+
+    /// Sets how many committed offsets of consumer groups are kept
+    pub fn with_max_groups(mut self, max_groups: usize) -> VecDequeMailbox {
+        self.max_groups = max_groups;
+        self
+    }
+
+    /// Sets the buffer size of subscribers and what happens when a subscriber does not keep up
+    pub fn with_subscribers(
+        mut self,
+        buffer: usize,
+        policy: SlowSubscriberPolicy,
+    ) -> VecDequeMailbox {
+        self.data.get_mut().unwrap().subscribers = Subscribers::new(buffer, policy);
+        self
+    }
+
+    /// Sets how long a publisher waits for a subscriber with [SlowSubscriberPolicy::Backpressure]
+    /// before the subscriber is disconnected
+    pub fn with_backpressure_timeout(mut self, timeout: Duration) -> VecDequeMailbox {
+        self.data
+            .get_mut()
+            .unwrap()
+            .subscribers
+            .set_timeout(timeout);
+        self
+    }
+
+    /// Sets how long and how many idempotency keys of PUBLISH are remembered
+    pub fn with_deduplication(mut self, window: Duration, capacity: usize) -> VecDequeMailbox {
+        self.data.get_mut().unwrap().deduplication = Deduplication::new(window, capacity);
+        self
+    }
+
+    /// Locks the data, also if a panic poisoned the lock,
+    /// so one failing command does not fail every later command of all clients
+    fn data(&self) -> MutexGuard<'_, Data> {
+        self.data.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Parses `drop-oldest`, `reject-new` or `max-age=<seconds>`
+impl FromStr for EvictionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            None if s == "drop-oldest" => Ok(EvictionPolicy::DropOldest),
+            None if s == "reject-new" => Ok(EvictionPolicy::RejectNew),
+            Some(("max-age", seconds)) => match seconds.parse() {
+                Ok(seconds) if seconds <= MAX_DURATION_SECS => {
+                    Ok(EvictionPolicy::MaxAge(Duration::from_secs(seconds)))
+                }
+                Ok(_) => Err(format!(
+                    "invalid max-age {}: at most {} seconds",
+                    seconds, MAX_DURATION_SECS
+                )),
+                Err(err) => Err(format!("invalid max-age {}: {}", seconds, err)),
+            },
+            _ => Err(format!(
+                "unknown policy {}, expected drop-oldest, reject-new or max-age=<seconds>",
+                s
+            )),
+        }
+    }
+}
+
+impl From<SocketAddr> for PeerAddr {
+    fn from(addr: SocketAddr) -> Self {
+        PeerAddr::Tcp(addr)
+    }
+}
+
+/// Formats the address as `<ip>:<port>` or `unix`
+impl fmt::Display for PeerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PeerAddr::Tcp(addr) => addr.fmt(f),
+            PeerAddr::Unix => write!(f, "unix"),
+        }
+    }
+}
+
+/// Formats the message as `<id> <received_at> <peer_addr> <client_name> <channel> <body>`,
+/// `received_at` is in milliseconds since the unix epoch and a missing client name is `-`
+impl fmt::Display for StoredMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let received_at = self
+            .received_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        write!(
+            f,
+            "{} {} {} {} {} {}",
+            self.id,
+            received_at,
+            self.peer_addr,
+            self.client_name.as_deref().unwrap_or("-"),
+            self.channel,
+            self.body
+        )
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Full => write!(f, "Mailbox error, mailbox is full"),
+            Error::UnknownChannel(channel) => {
+                write!(f, "Mailbox error, unknown channel: {}", channel)
+            }
+            Error::ChannelExists(channel) => {
+                write!(f, "Mailbox error, channel already exists: {}", channel)
+            }
+            Error::DefaultChannel => {
+                write!(f, "Mailbox error, the default channel cannot be deleted")
+            }
+            Error::NotLeased(id) => write!(f, "Mailbox error, email is not leased: {}", id),
+            Error::UnknownEmail(id) => write!(f, "Mailbox error, unknown email: {}", id),
+            Error::InvalidQuery(err) => write!(f, "Mailbox error, invalid query: {}", err),
+            Error::TimeOutOfRange => write!(f, "Mailbox error, time is out of range"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn sender() -> Sender {
+        Sender {
+            peer_addr: PeerAddr::Tcp("127.0.0.1:4000".parse().unwrap()),
+            client_name: None,
+        }
+    }
+
+    pub(super) fn publish_to(channel: &str, message: &str) -> Publish {
+        Publish {
+            channel: Some(channel.to_owned()),
+            ..message.into()
+        }
+    }
+
+    #[tokio::test]
+    async fn mailbox_keeps_serving_after_a_panic_while_locked() {
+        let mailbox = Arc::new(VecDequeMailbox::new(10, EvictionPolicy::DropOldest));
+        let panicking = mailbox.clone();
+        let result = std::thread::spawn(move || {
+            let _data = panicking.data();
+            panic!("bug while the lock is held");
+        })
+        .join();
+        assert!(result.is_err());
+        assert!(mailbox.data.is_poisoned());
+
+        mailbox.append(&sender(), &"hello".into()).await.unwrap();
+        assert_eq!(&*mailbox.list_emails().await, "hello;");
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_keeps_newest_emails() {
+        let mailbox = VecDequeMailbox::new(2, EvictionPolicy::DropOldest);
+        for email in &["a", "b", "c"] {
+            assert!(mailbox.append(&sender(), &(*email).into()).await.is_ok());
+        }
+        assert_eq!(&*mailbox.list_emails().await, "c;b;");
+        assert_eq!(mailbox.evicted().await, 1);
+    }
+
+    #[tokio::test]
+    async fn reject_new_errors_when_full() {
+        let mailbox = VecDequeMailbox::new(2, EvictionPolicy::RejectNew);
+        assert_eq!(mailbox.append(&sender(), &"a".into()).await, Ok(Some(1)));
+        assert_eq!(mailbox.append(&sender(), &"b".into()).await, Ok(Some(2)));
+        assert_eq!(
+            mailbox.append(&sender(), &"c".into()).await,
+            Err(Error::Full)
+        );
+        assert_eq!(&*mailbox.list_emails().await, "b;a;");
+        assert_eq!(mailbox.evicted().await, 0);
+    }
+
+    #[tokio::test]
+    async fn max_age_drops_old_emails() {
+        let mailbox = VecDequeMailbox::new(10, EvictionPolicy::MaxAge(Duration::from_millis(50)));
+        mailbox.append(&sender(), &"old".into()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        mailbox.append(&sender(), &"new".into()).await.unwrap();
+        assert_eq!(&*mailbox.list_emails().await, "new;");
+        assert_eq!(mailbox.evicted().await, 1);
+    }
+
+    #[tokio::test]
+    async fn messages_have_metadata() {
+        let mailbox = VecDequeMailbox::new(10, EvictionPolicy::DropOldest);
+        let named = Sender {
+            client_name: Some("tui".to_owned()),
+            ..sender()
+        };
+        mailbox.append(&sender(), &"first".into()).await.unwrap();
+        mailbox
+            .append(&named, &"second email".into())
+            .await
+            .unwrap();
+
+        let messages = mailbox.list_messages().await;
+        let ids: Vec<u64> = messages.iter().map(|it| it.id).collect();
+        assert_eq!(ids, vec![2, 1]);
+        assert_eq!(messages[0].client_name.as_deref(), Some("tui"));
+        assert!(messages[0].received_at >= messages[1].received_at);
+
+        let line = messages[0].to_string();
+        assert!(line.starts_with("2 "));
+        assert!(line.ends_with(" 127.0.0.1:4000 tui misc second email"));
+        assert!(messages[1]
+            .to_string()
+            .ends_with(" 127.0.0.1:4000 - misc first"));
+    }
+
+    #[tokio::test]
+    async fn channels_count_emails() {
+        let mailbox = VecDequeMailbox::new(2, EvictionPolicy::DropOldest);
+        let to_general = publish_to("general", "hi");
+        assert_eq!(
+            mailbox.append(&sender(), &to_general).await,
+            Err(Error::UnknownChannel("general".to_owned()))
+        );
+        assert_eq!(mailbox.create_channel("general").await, Ok(()));
+        assert_eq!(
+            mailbox.create_channel("general").await,
+            Err(Error::ChannelExists("general".to_owned()))
+        );
+
+        mailbox.append(&sender(), &"a".into()).await.unwrap();
+        mailbox.append(&sender(), &to_general).await.unwrap();
+        assert_eq!(
+            mailbox.list_channels().await,
+            vec![("general".to_owned(), 1), ("misc".to_owned(), 1)]
+        );
+
+        // evicts "a" from misc
+        mailbox.append(&sender(), &to_general).await.unwrap();
+        assert_eq!(
+            mailbox.list_channels().await,
+            vec![("general".to_owned(), 2), ("misc".to_owned(), 0)]
+        );
+    }
+
+    #[tokio::test]
+    async fn delete_channel_removes_emails() {
+        let mailbox = VecDequeMailbox::new(10, EvictionPolicy::DropOldest);
+        mailbox.create_channel("general").await.unwrap();
+        let to_general = publish_to("general", "hi");
+        mailbox.append(&sender(), &to_general).await.unwrap();
+        mailbox.append(&sender(), &"a".into()).await.unwrap();
+
+        assert_eq!(mailbox.stored_bytes().await, 3);
+        assert_eq!(mailbox.delete_channel("general").await, Ok(()));
+        assert_eq!(&*mailbox.list_emails().await, "a;");
+        assert_eq!(mailbox.stored_bytes().await, 1);
+        assert_eq!(
+            mailbox.delete_channel("general").await,
+            Err(Error::UnknownChannel("general".to_owned()))
+        );
+        assert_eq!(
+            mailbox.delete_channel(DEFAULT_CHANNEL).await,
+            Err(Error::DefaultChannel)
+        );
+    }
+
+    #[tokio::test]
+    async fn list_messages_since_returns_newer_emails_of_all_channels() {
+        let mailbox = VecDequeMailbox::new(10, EvictionPolicy::DropOldest);
+        mailbox.create_channel("general").await.unwrap();
+        for message in &["a", "b", "c"] {
+            let publish = publish_to("general", message);
+            mailbox.append(&sender(), &publish).await.unwrap();
+        }
+        mailbox.append(&sender(), &"misc".into()).await.unwrap();
+
+        let messages = mailbox.list_messages_since(2).await;
+        let bodies: Vec<&str> = messages.iter().map(|it| it.body.as_str()).collect();
+        assert_eq!(bodies, vec!["misc", "c"]);
+        assert_eq!(mailbox.list_messages_since(4).await, vec![]);
+        assert_eq!(mailbox.list_messages_since(0).await.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn messages_since_returns_newer_emails_of_the_channel() {
+        let mailbox = VecDequeMailbox::new(10, EvictionPolicy::DropOldest);
+        mailbox.create_channel("general").await.unwrap();
+        for message in &["a", "b", "c"] {
+            let publish = publish_to("general", message);
+            mailbox.append(&sender(), &publish).await.unwrap();
+            mailbox.append(&sender(), &"misc".into()).await.unwrap();
+        }
+
+        let messages = mailbox.messages_since("general", 1).await.unwrap();
+        let ids: Vec<u64> = messages.iter().map(|it| it.id).collect();
+        assert_eq!(ids, vec![3, 5]);
+        assert_eq!(mailbox.messages_since("general", 5).await, Ok(vec![]));
+        assert_eq!(
+            mailbox.messages_since("other", 0).await,
+            Err(Error::UnknownChannel("other".to_owned()))
+        );
+    }
+
+    #[tokio::test]
+    async fn ordered_reads_return_higher_priority_first() {
+        let mailbox = VecDequeMailbox::new(10, EvictionPolicy::DropOldest);
+        for (message, priority) in &[
+            ("chatter", 0),
+            ("alert", 9),
+            ("more chatter", 0),
+            ("warning", 5),
+        ] {
+            let publish = Publish {
+                priority: *priority,
+                ..(*message).into()
+            };
+            mailbox.append(&sender(), &publish).await.unwrap();
+        }
+        let mut messages = mailbox.list_messages().await;
+        sort_by_priority(&mut messages);
+        let bodies: Vec<&str> = messages.iter().map(|email| email.body.as_str()).collect();
+        assert_eq!(bodies, vec!["alert", "warning", "chatter", "more chatter"]);
+    }
+
+    #[test]
+    fn parse_eviction_policy() {
+        assert_eq!("drop-oldest".parse(), Ok(EvictionPolicy::DropOldest));
+        assert_eq!("reject-new".parse(), Ok(EvictionPolicy::RejectNew));
+        assert_eq!(
+            "max-age=60".parse(),
+            Ok(EvictionPolicy::MaxAge(Duration::from_secs(60)))
+        );
+        assert!("max-age=soon".parse::<EvictionPolicy>().is_err());
+        let too_long = format!("max-age={}", MAX_DURATION_SECS + 1);
+        assert!(too_long.parse::<EvictionPolicy>().is_err());
+        assert!("drop-newest".parse::<EvictionPolicy>().is_err());
+    }
+}
@@ -0,0 +1,187 @@
+use std::time::SystemTime;
+
+use redisish::Schedule;
+
+use super::{Data, Error, Sender, StoredMessage, VecDequeMailbox};
+
+/// An email published with DELAY or AT which is not visible to readers yet
+pub(super) struct Scheduled {
+    pub(super) sender: Sender,
+    pub(super) channel: String,
+    pub(super) body: String,
+    pub(super) expires_at: Option<SystemTime>,
+    pub(super) priority: u8,
+    pub(super) idempotency_key: Option<String>,
+}
+
+/// Time at which an email published at `now` becomes visible,
+/// [Error::TimeOutOfRange] if the delay reaches beyond the representable time
+pub(super) fn visible_at(schedule: Schedule, now: SystemTime) -> Result<SystemTime, Error> {
+    match schedule {
+        Schedule::Delay(delay) => now.checked_add(delay).ok_or(Error::TimeOutOfRange),
+        Schedule::At(at) => Ok(at),
+    }
+}
+
+/// Delayed delivery: scheduled emails wait in a queue ordered by the time they become visible.
+/// They are promoted lazily whenever the mailbox is accessed and get their id only then,
+/// so ids keep growing in the order in which readers see the emails.
+impl Data {
+    /// Stores an email which becomes visible at the given time
+    pub(super) fn schedule(&mut self, visible_at: SystemTime, email: Scheduled) {
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        self.scheduled.insert((visible_at, sequence), email);
+    }
+
+    /// Makes an email visible and returns its id
+    pub(super) fn publish(&mut self, email: Scheduled, received_at: SystemTime) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        if let Some(key) = &email.idempotency_key {
+            self.deduplication.update(key, id);
+        }
+        let email = StoredMessage {
+            id,
+            received_at,
+            peer_addr: email.sender.peer_addr,
+            client_name: email.sender.client_name,
+            channel: email.channel,
+            body: email.body,
+            expires_at: email.expires_at,
+            priority: email.priority,
+        };
+        self.subscribers.publish(&email);
+        self.insert(email);
+        id
+    }
+
+    /// Publishes the scheduled emails which are due
+    fn promote_due(&mut self, now: SystemTime) {
+        while let Some(entry) = self.scheduled.first_entry() {
+            let (visible_at, _) = *entry.key();
+            if visible_at > now {
+                break;
+            }
+            let email = entry.remove();
+            self.publish(email, visible_at);
+        }
+    }
+}
+
+impl VecDequeMailbox {
+    /// Brings the mailbox up to date before it is read:
+    /// publishes due scheduled emails and drops expired and too old ones
+    pub(super) fn refresh(&self, data: &mut Data) {
+        let now = SystemTime::now();
+        data.promote_due(now);
+        data.remove_expired(now);
+        self.evict_too_old(data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use redisish::Publish;
+
+    use crate::mailbox::tests::{publish_to, sender};
+    use crate::mailbox::{Error, EvictionPolicy};
+
+    use super::*;
+
+    fn delayed(message: &str, millis: u64) -> Publish {
+        Publish {
+            schedule: Some(Schedule::Delay(Duration::from_millis(millis))),
+            ..message.into()
+        }
+    }
+
+    #[tokio::test]
+    async fn delayed_email_becomes_visible_later() {
+        let mailbox = VecDequeMailbox::new(10, EvictionPolicy::DropOldest);
+        assert_eq!(
+            mailbox.append(&sender(), &delayed("later", 50)).await,
+            Ok(None)
+        );
+        assert_eq!(mailbox.append(&sender(), &"now".into()).await, Ok(Some(1)));
+        assert_eq!(&*mailbox.list_emails().await, "now;");
+        assert_eq!(mailbox.list_channels().await[0], ("misc".to_owned(), 1));
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(&*mailbox.list_emails().await, "later;now;");
+        assert_eq!(mailbox.list_messages().await[0].id, 2);
+    }
+
+    #[tokio::test]
+    async fn delay_beyond_the_representable_time_is_rejected() {
+        let mailbox = VecDequeMailbox::new(10, EvictionPolicy::DropOldest);
+        let publish = Publish {
+            schedule: Some(Schedule::Delay(Duration::from_secs(u64::MAX))),
+            ..Publish::from("boom")
+        };
+        assert_eq!(
+            mailbox.append(&sender(), &publish).await,
+            Err(Error::TimeOutOfRange)
+        );
+        assert_eq!(mailbox.append(&sender(), &"now".into()).await, Ok(Some(1)));
+    }
+
+    #[tokio::test]
+    async fn emails_become_visible_in_scheduled_order() {
+        let mailbox = VecDequeMailbox::new(10, EvictionPolicy::DropOldest);
+        mailbox.append(&sender(), &delayed("b", 60)).await.unwrap();
+        mailbox.append(&sender(), &delayed("a", 30)).await.unwrap();
+        let past = Publish {
+            schedule: Some(Schedule::At(UNIX_EPOCH)),
+            ..publish_to("misc", "c")
+        };
+        assert_eq!(mailbox.append(&sender(), &past).await, Ok(Some(1)));
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let messages = mailbox.list_messages().await;
+        let emails: Vec<(u64, &str)> = messages
+            .iter()
+            .map(|email| (email.id, email.body.as_str()))
+            .collect();
+        assert_eq!(emails, vec![(3, "b"), (2, "a"), (1, "c")]);
+    }
+
+    #[tokio::test]
+    async fn scheduled_emails_count_towards_capacity() {
+        let mailbox = VecDequeMailbox::new(1, EvictionPolicy::RejectNew);
+        mailbox
+            .append(&sender(), &delayed("later", 1_000))
+            .await
+            .unwrap();
+        assert_eq!(
+            mailbox.append(&sender(), &"now".into()).await,
+            Err(Error::Full)
+        );
+
+        let mailbox = VecDequeMailbox::new(1, EvictionPolicy::DropOldest);
+        mailbox
+            .append(&sender(), &delayed("later", 1_000))
+            .await
+            .unwrap();
+        mailbox.append(&sender(), &"now".into()).await.unwrap();
+        assert_eq!(mailbox.evicted().await, 1);
+    }
+
+    #[tokio::test]
+    async fn deleting_channel_drops_scheduled_emails() {
+        let mailbox = VecDequeMailbox::new(10, EvictionPolicy::DropOldest);
+        mailbox.create_channel("general").await.unwrap();
+        let publish = Publish {
+            channel: Some("general".to_owned()),
+            ..delayed("later", 30)
+        };
+        mailbox.append(&sender(), &publish).await.unwrap();
+        mailbox.delete_channel("general").await.unwrap();
+        mailbox.create_channel("general").await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(&*mailbox.list_emails().await, "");
+    }
+}
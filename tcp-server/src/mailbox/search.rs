@@ -0,0 +1,225 @@
+use std::collections::{BTreeSet, HashMap};
+
+use regex::Regex;
+
+use redisish::SearchMode;
+
+use super::{Error, StoredMessage, VecDequeMailbox};
+
+/// Inverted index from the trigrams of the lowercase email bodies to email ids.
+/// It is updated whenever an email is stored or removed, so a search only verifies
+/// the emails which contain all trigrams of the query instead of scanning the mailbox.
+#[derive(Default)]
+pub(super) struct SearchIndex {
+    postings: HashMap<[char; 3], BTreeSet<u64>>,
+}
+
+/// Distinct trigrams of the lowercase text
+fn trigrams(text: &str) -> BTreeSet<[char; 3]> {
+    let chars: Vec<char> = text.to_lowercase().chars().collect();
+    chars
+        .windows(3)
+        .map(|window| [window[0], window[1], window[2]])
+        .collect()
+}
+
+impl SearchIndex {
+    pub(super) fn insert(&mut self, email: &StoredMessage) {
+        for trigram in trigrams(&email.body) {
+            self.postings.entry(trigram).or_default().insert(email.id);
+        }
+    }
+
+    pub(super) fn remove(&mut self, email: &StoredMessage) {
+        for trigram in trigrams(&email.body) {
+            if let Some(ids) = self.postings.get_mut(&trigram) {
+                ids.remove(&email.id);
+                if ids.is_empty() {
+                    self.postings.remove(&trigram);
+                }
+            }
+        }
+    }
+
+    /// Ids of the emails which may contain the text in any case,
+    /// `None` if the text is too short to use the index
+    fn candidates(&self, text: &str) -> Option<BTreeSet<u64>> {
+        let mut candidates: Option<BTreeSet<u64>> = None;
+        for trigram in trigrams(text) {
+            let ids = match self.postings.get(&trigram) {
+                Some(ids) => ids,
+                None => return Some(BTreeSet::new()),
+            };
+            candidates = Some(match candidates {
+                None => ids.clone(),
+                Some(candidates) => candidates.intersection(ids).copied().collect(),
+            });
+        }
+        candidates
+    }
+}
+
+/// A compiled SEARCH query
+enum Matcher {
+    Substring(String),
+    IgnoreCase(String),
+    Regex(Regex),
+}
+
+impl Matcher {
+    fn new(mode: SearchMode, query: &str) -> Result<Matcher, Error> {
+        Ok(match mode {
+            SearchMode::Substring => Matcher::Substring(query.to_owned()),
+            SearchMode::IgnoreCase => Matcher::IgnoreCase(query.to_lowercase()),
+            SearchMode::Regex => Matcher::Regex(
+                Regex::new(query).map_err(|err| Error::InvalidQuery(err.to_string()))?,
+            ),
+        })
+    }
+
+    fn is_match(&self, body: &str) -> bool {
+        match self {
+            Matcher::Substring(query) => body.contains(query.as_str()),
+            Matcher::IgnoreCase(query) => body.to_lowercase().contains(query.as_str()),
+            Matcher::Regex(regex) => regex.is_match(body),
+        }
+    }
+
+    /// Text which every matching body contains, regular expressions are verified one by one
+    fn text(&self) -> Option<&str> {
+        match self {
+            Matcher::Substring(query) | Matcher::IgnoreCase(query) => Some(query),
+            Matcher::Regex(_) => None,
+        }
+    }
+}
+
+impl VecDequeMailbox {
+    /// Returns the emails of the channel matching the query, newest first
+    pub async fn search(
+        &self,
+        channel: &str,
+        mode: SearchMode,
+        query: &str,
+    ) -> Result<Vec<StoredMessage>, Error> {
+        let matcher = Matcher::new(mode, query)?;
+        let mut data = self.data();
+        if !data.channels.contains_key(channel) {
+            return Err(Error::UnknownChannel(channel.to_owned()));
+        }
+        self.refresh(&mut data);
+        let is_match =
+            |email: &StoredMessage| email.channel == channel && matcher.is_match(&email.body);
+        let candidates = matcher
+            .text()
+            .and_then(|text| data.search_index.candidates(text));
+        Ok(match candidates {
+            Some(ids) => ids
+                .iter()
+                .rev()
+                .filter_map(|id| data.get(*id))
+                .filter(|email| is_match(email))
+                .cloned()
+                .collect(),
+            None => data
+                .emails
+                .iter()
+                .filter(|email| is_match(email))
+                .cloned()
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mailbox::tests::{publish_to, sender};
+    use crate::mailbox::EvictionPolicy;
+
+    use super::*;
+
+    async fn mailbox_with_alerts() -> VecDequeMailbox {
+        let mailbox = VecDequeMailbox::new(10, EvictionPolicy::DropOldest);
+        mailbox.create_channel("alerts").await.unwrap();
+        for message in &["Disk full on db1", "disk 80% on web2", "CPU high on db1"] {
+            let publish = publish_to("alerts", message);
+            mailbox.append(&sender(), &publish).await.unwrap();
+        }
+        mailbox
+            .append(&sender(), &"disk full".into())
+            .await
+            .unwrap();
+        mailbox
+    }
+
+    async fn search(mailbox: &VecDequeMailbox, mode: SearchMode, query: &str) -> Vec<String> {
+        let emails = mailbox.search("alerts", mode, query).await.unwrap();
+        emails
+            .into_iter()
+            .map(|email| format!("{} {}", email.id, email.body))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn search_modes() {
+        let mailbox = mailbox_with_alerts().await;
+        assert_eq!(
+            search(&mailbox, SearchMode::Substring, "disk").await,
+            vec!["2 disk 80% on web2"]
+        );
+        assert_eq!(
+            search(&mailbox, SearchMode::IgnoreCase, "DISK").await,
+            vec!["2 disk 80% on web2", "1 Disk full on db1"]
+        );
+        assert_eq!(
+            search(&mailbox, SearchMode::Regex, "on (db|web)[0-9]$").await,
+            vec![
+                "3 CPU high on db1",
+                "2 disk 80% on web2",
+                "1 Disk full on db1"
+            ]
+        );
+        assert_eq!(
+            search(&mailbox, SearchMode::Substring, "on").await,
+            vec![
+                "3 CPU high on db1",
+                "2 disk 80% on web2",
+                "1 Disk full on db1"
+            ]
+        );
+        assert!(search(&mailbox, SearchMode::Substring, "memory")
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn search_index_follows_removed_emails() {
+        let mailbox = mailbox_with_alerts().await;
+        let email = mailbox.fetch("alerts").await.unwrap().unwrap();
+        mailbox.ack(email.id).await.unwrap();
+        assert_eq!(
+            search(&mailbox, SearchMode::Substring, "db1").await,
+            vec!["3 CPU high on db1"]
+        );
+        mailbox.delete_channel("alerts").await.unwrap();
+        mailbox.create_channel("alerts").await.unwrap();
+        assert!(search(&mailbox, SearchMode::Substring, "db1")
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn search_errors() {
+        let mailbox = mailbox_with_alerts().await;
+        assert_eq!(
+            mailbox
+                .search("unknown", SearchMode::Substring, "disk")
+                .await,
+            Err(Error::UnknownChannel("unknown".to_owned()))
+        );
+        assert!(matches!(
+            mailbox.search("alerts", SearchMode::Regex, "(").await,
+            Err(Error::InvalidQuery(_))
+        ));
+    }
+}
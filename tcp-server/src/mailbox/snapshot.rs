@@ -0,0 +1,137 @@
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use arc_swap::ArcSwapOption;
+
+use super::{Data, EvictionPolicy, VecDequeMailbox};
+
+/// The encoded reply of the legacy RETRIEVE, shared by all readers until the emails change.
+/// Readers load it without taking the mailbox lock, so concurrent RETRIEVEs do not queue up
+/// behind each other and the emails are only encoded once per change.
+#[derive(Default)]
+pub(super) struct Snapshots {
+    current: ArcSwapOption<Snapshot>,
+}
+
+struct Snapshot {
+    encoded: Arc<str>,
+    /// When the next scheduled email becomes visible or the next email expires or gets too old
+    valid_until: Option<SystemTime>,
+}
+
+impl Snapshots {
+    fn get(&self, now: SystemTime) -> Option<Arc<str>> {
+        let snapshot = self.current.load();
+        match snapshot.as_deref() {
+            Some(snapshot) if snapshot.valid_until.is_none_or(|until| now < until) => {
+                Some(snapshot.encoded.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// Drops the snapshot, called with the mailbox lock held whenever an email is stored or removed
+    pub(super) fn invalidate(&self) {
+        self.current.store(None);
+    }
+}
+
+impl Data {
+    /// Bodies of the emails, newest first, each followed by `;`
+    fn encode(&self) -> String {
+        let mut encoded = String::with_capacity(self.bytes + self.emails.len());
+        for email in &self.emails {
+            encoded.push_str(&email.body);
+            encoded.push(';');
+        }
+        encoded
+    }
+}
+
+impl VecDequeMailbox {
+    /// Returns a list of emails as a string, separated by `;`
+    pub async fn list_emails(&self) -> Arc<str> {
+        if let Some(encoded) = self.snapshots.get(SystemTime::now()) {
+            return encoded;
+        }
+        let mut data = self.data();
+        self.refresh(&mut data);
+        let encoded: Arc<str> = data.encode().into();
+        // stored while holding the lock, so a concurrent change invalidates it afterwards
+        self.snapshots.current.store(Some(Arc::new(Snapshot {
+            encoded: encoded.clone(),
+            valid_until: self.next_change(&data),
+        })));
+        encoded
+    }
+
+    /// The earliest time at which [VecDequeMailbox::refresh] changes the emails
+    fn next_change(&self, data: &Data) -> Option<SystemTime> {
+        let visible = data
+            .scheduled
+            .keys()
+            .next()
+            .map(|(visible_at, _)| *visible_at);
+        let expires = data
+            .expiring
+            .iter()
+            .next()
+            .map(|(expires_at, _)| *expires_at);
+        let too_old = match self.policy {
+            EvictionPolicy::MaxAge(max_age) => data
+                .emails
+                .back()
+                // never too old if the time is beyond the representable time
+                .and_then(|oldest| oldest.received_at.checked_add(max_age)),
+            _ => None,
+        };
+        [visible, expires, too_old].iter().flatten().min().copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use redisish::{Publish, Schedule};
+
+    use crate::mailbox::tests::sender;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn snapshot_is_shared_until_the_emails_change() {
+        let mailbox = VecDequeMailbox::new(10, EvictionPolicy::DropOldest);
+        mailbox.append(&sender(), &"a".into()).await.unwrap();
+        let first = mailbox.list_emails().await;
+        assert!(Arc::ptr_eq(&first, &mailbox.list_emails().await));
+
+        mailbox.list_channels().await;
+        assert!(Arc::ptr_eq(&first, &mailbox.list_emails().await));
+
+        mailbox.append(&sender(), &"b".into()).await.unwrap();
+        assert_eq!(&*mailbox.list_emails().await, "b;a;");
+    }
+
+    #[tokio::test]
+    async fn snapshot_expires_when_a_scheduled_email_becomes_visible() {
+        let mailbox = VecDequeMailbox::new(10, EvictionPolicy::DropOldest);
+        let delayed = Publish {
+            schedule: Some(Schedule::Delay(Duration::from_millis(50))),
+            .."later".into()
+        };
+        mailbox.append(&sender(), &delayed).await.unwrap();
+        assert_eq!(&*mailbox.list_emails().await, "");
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(&*mailbox.list_emails().await, "later;");
+    }
+
+    #[tokio::test]
+    async fn max_age_beyond_the_representable_time_never_expires_the_snapshot() {
+        let mailbox = VecDequeMailbox::new(10, EvictionPolicy::MaxAge(Duration::MAX));
+        mailbox.append(&sender(), &"a".into()).await.unwrap();
+        assert_eq!(&*mailbox.list_emails().await, "a;");
+        assert_eq!(&*mailbox.list_emails().await, "a;");
+    }
+}
@@ -0,0 +1,552 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use event_listener::Event;
+
+use super::timer::wait_until;
+use super::{Error, StoredMessage, VecDequeMailbox};
+
+/// Number of emails buffered per subscriber unless configured otherwise
+pub const DEFAULT_SUBSCRIBER_BUFFER: usize = 1_000;
+
+/// How long a publisher waits for a slow subscriber unless configured otherwise
+pub const DEFAULT_BACKPRESSURE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Decides what happens when a subscriber does not keep up and its buffer is full
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum SlowSubscriberPolicy {
+    /// The oldest buffered email is dropped to make space for the new one
+    DropOldest,
+    /// The subscriber is disconnected
+    Disconnect,
+    /// Publishers to the channel wait until the subscriber catches up.
+    /// A subscriber which keeps a publisher waiting too long is disconnected.
+    Backpressure,
+}
+
+/// A client which receives the emails published to its channels.
+/// Emails are buffered until the client takes them, higher priority first.
+pub struct Subscriber {
+    pub id: u64,
+    state: Mutex<State>,
+    /// Wakes up the subscriber when an email arrives or it is disconnected
+    published: Event,
+    /// Wakes up waiting publishers when the subscriber took an email
+    taken: Event,
+}
+
+#[derive(Default)]
+struct State {
+    channels: BTreeSet<String>,
+    buffer: VecDeque<StoredMessage>,
+    dropped: u64,
+    disconnected: bool,
+}
+
+/// Subscriber metrics, a subscriber lags if its buffer is at least half full
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct SubscriberStats {
+    pub id: u64,
+    pub channels: Vec<String>,
+    pub buffered: usize,
+    pub dropped: u64,
+    pub lagging: bool,
+}
+
+/// Subscribers of all channels
+pub(super) struct Subscribers {
+    buffer: usize,
+    policy: SlowSubscriberPolicy,
+    /// How long a publisher waits for a subscriber with [SlowSubscriberPolicy::Backpressure]
+    timeout: Duration,
+    subscribers: Vec<Arc<Subscriber>>,
+    next_id: u64,
+    disconnected: u64,
+}
+
+impl Subscriber {
+    /// Waits for the next email, `None` if the subscriber was disconnected
+    pub async fn next(&self) -> Option<StoredMessage> {
+        loop {
+            // listening before checking the buffer, so no email is missed in between
+            let published = self.published.listen();
+            {
+                let mut state = self.state();
+                if state.disconnected {
+                    return None;
+                }
+                let position = (0..state.buffer.len())
+                    .max_by_key(|position| (state.buffer[*position].priority, Reverse(*position)));
+                if let Some(position) = position {
+                    let email = state.buffer.remove(position);
+                    self.taken.notify(usize::MAX);
+                    return email;
+                }
+            }
+            published.await;
+        }
+    }
+
+    fn is_full(&self, capacity: usize) -> bool {
+        self.state().buffer.len() >= capacity
+    }
+
+    fn is_subscribed(&self, channel: &str) -> bool {
+        self.state().channels.contains(channel)
+    }
+
+    /// Stops the subscriber, [Subscriber::next] returns `None` from now on
+    pub fn disconnect(&self) {
+        self.state().disconnected = true;
+        self.published.notify(1);
+    }
+
+    /// Locks the state, also if a panic poisoned the lock,
+    /// so one failing connection does not fail the publishers of its channels
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Subscribers {
+    pub(super) fn new(buffer: usize, policy: SlowSubscriberPolicy) -> Subscribers {
+        Subscribers {
+            buffer,
+            policy,
+            timeout: DEFAULT_BACKPRESSURE_TIMEOUT,
+            subscribers: vec![],
+            next_id: 1,
+            disconnected: 0,
+        }
+    }
+
+    /// Hands a newly visible email to the subscribers of its channel
+    pub(super) fn publish(&mut self, email: &StoredMessage) {
+        let capacity = self.buffer;
+        let policy = self.policy;
+        let mut disconnected = 0;
+        self.subscribers.retain(|subscriber| {
+            let mut state = subscriber.state();
+            if !state.channels.contains(&email.channel) {
+                return true;
+            }
+            if state.buffer.len() >= capacity {
+                match policy {
+                    SlowSubscriberPolicy::DropOldest => {
+                        state.buffer.pop_front();
+                        state.dropped += 1;
+                    }
+                    SlowSubscriberPolicy::Disconnect => {
+                        drop(state);
+                        subscriber.disconnect();
+                        disconnected += 1;
+                        return false;
+                    }
+                    // publishers wait before they append, scheduled emails may overshoot
+                    SlowSubscriberPolicy::Backpressure => {}
+                }
+            }
+            state.buffer.push_back(email.clone());
+            drop(state);
+            subscriber.published.notify(1);
+            true
+        });
+        self.disconnected += disconnected;
+    }
+
+    /// Sets how long a publisher waits for a slow subscriber before it is disconnected
+    pub(super) fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Disconnects a subscriber which kept a publisher waiting too long
+    fn disconnect_slow(&mut self, subscriber: &Arc<Subscriber>) {
+        let subscribed = self.subscribers.len();
+        self.subscribers.retain(|it| !Arc::ptr_eq(it, subscriber));
+        if self.subscribers.len() < subscribed {
+            subscriber.disconnect();
+            self.disconnected += 1;
+        }
+    }
+
+    /// Stops delivering the emails of a deleted channel
+    pub(super) fn remove_channel(&mut self, channel: &str) {
+        for subscriber in &self.subscribers {
+            let mut state = subscriber.state();
+            state.channels.remove(channel);
+            state.buffer.retain(|email| email.channel != channel);
+        }
+    }
+}
+
+/// Pub/sub: subscribers receive the emails of their channels as soon as they become visible.
+/// Every subscriber has a bounded buffer, the [SlowSubscriberPolicy] decides what happens
+/// when a subscriber does not keep up.
+impl VecDequeMailbox {
+    /// Creates a subscriber which is not subscribed to any channel yet
+    pub async fn subscriber(&self) -> Arc<Subscriber> {
+        let mut data = self.data();
+        let id = data.subscribers.next_id;
+        data.subscribers.next_id += 1;
+        Arc::new(Subscriber {
+            id,
+            state: Mutex::new(State::default()),
+            published: Event::new(),
+            taken: Event::new(),
+        })
+    }
+
+    /// Delivers the emails published to the channel from now on to the subscriber
+    pub async fn subscribe(
+        &self,
+        subscriber: &Arc<Subscriber>,
+        channel: &str,
+    ) -> Result<(), Error> {
+        let mut data = self.data();
+        if !data.channels.contains_key(channel) {
+            return Err(Error::UnknownChannel(channel.to_owned()));
+        }
+        subscriber
+            .state
+            .lock()
+            .unwrap()
+            .channels
+            .insert(channel.to_owned());
+        let subscribers = &mut data.subscribers.subscribers;
+        if !subscribers.iter().any(|it| Arc::ptr_eq(it, subscriber)) {
+            subscribers.push(subscriber.clone());
+        }
+        Ok(())
+    }
+
+    /// Stops delivering emails to the subscriber
+    pub async fn unsubscribe(&self, subscriber: &Arc<Subscriber>) {
+        let mut data = self.data();
+        data.subscribers
+            .subscribers
+            .retain(|it| !Arc::ptr_eq(it, subscriber));
+    }
+
+    /// Metrics of the current subscribers
+    pub async fn subscriber_stats(&self) -> Vec<SubscriberStats> {
+        let data = self.data();
+        let capacity = data.subscribers.buffer;
+        data.subscribers
+            .subscribers
+            .iter()
+            .map(|subscriber| {
+                let state = subscriber.state();
+                SubscriberStats {
+                    id: subscriber.id,
+                    channels: state.channels.iter().cloned().collect(),
+                    buffered: state.buffer.len(),
+                    dropped: state.dropped,
+                    lagging: state.buffer.len() * 2 >= capacity,
+                }
+            })
+            .collect()
+    }
+
+    /// Number of subscribers disconnected by [SlowSubscriberPolicy::Disconnect] since the start
+    pub async fn disconnected_subscribers(&self) -> u64 {
+        self.data().subscribers.disconnected
+    }
+
+    /// Waits while a subscriber of the channel has a full buffer
+    /// and the policy is [SlowSubscriberPolicy::Backpressure].
+    /// The subscriber of the publishing connection is skipped, it cannot take emails while its
+    /// connection waits. A subscriber which is still full after the timeout is disconnected.
+    pub(super) async fn wait_for_subscribers(&self, channel: &str, own: Option<&Subscriber>) {
+        // deadline by subscriber, counted from when this publisher found it full,
+        // `None` if the timeout is beyond the representable time
+        let mut deadlines = HashMap::new();
+        loop {
+            let (full, timeout) = {
+                let data = self.data();
+                let subscribers = &data.subscribers;
+                if subscribers.policy != SlowSubscriberPolicy::Backpressure {
+                    return;
+                }
+                let full = subscribers.subscribers.iter().find(|it| {
+                    own.is_none_or(|own| own.id != it.id)
+                        && it.is_subscribed(channel)
+                        && it.is_full(subscribers.buffer)
+                });
+                (full.cloned(), subscribers.timeout)
+            };
+            let subscriber = match full {
+                Some(subscriber) => subscriber,
+                None => return,
+            };
+            let taken = subscriber.taken.listen();
+            let buffer = self.data().subscribers.buffer;
+            if !subscriber.is_full(buffer) {
+                continue;
+            }
+            let deadline = *deadlines
+                .entry(subscriber.id)
+                .or_insert_with(|| Instant::now().checked_add(timeout));
+            match deadline {
+                Some(deadline) => {
+                    if wait_until(deadline, taken).await.is_none() {
+                        self.data().subscribers.disconnect_slow(&subscriber);
+                    }
+                }
+                None => taken.await,
+            }
+        }
+    }
+}
+
+/// Parses `drop-oldest`, `disconnect` or `backpressure`
+impl FromStr for SlowSubscriberPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop-oldest" => Ok(SlowSubscriberPolicy::DropOldest),
+            "disconnect" => Ok(SlowSubscriberPolicy::Disconnect),
+            "backpressure" => Ok(SlowSubscriberPolicy::Backpressure),
+            _ => Err(format!(
+                "unknown policy {}, expected drop-oldest, disconnect or backpressure",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use redisish::Publish;
+
+    use crate::mailbox::tests::{publish_to, sender};
+    use crate::mailbox::EvictionPolicy;
+
+    use super::*;
+
+    async fn mailbox_with_subscriber(
+        policy: SlowSubscriberPolicy,
+    ) -> (Arc<VecDequeMailbox>, Arc<Subscriber>) {
+        let mailbox =
+            VecDequeMailbox::new(10, EvictionPolicy::DropOldest).with_subscribers(2, policy);
+        mailbox.create_channel("general").await.unwrap();
+        let subscriber = mailbox.subscriber().await;
+        mailbox.subscribe(&subscriber, "general").await.unwrap();
+        (Arc::new(mailbox), subscriber)
+    }
+
+    async fn publish(mailbox: &VecDequeMailbox, message: &str) {
+        let publish = publish_to("general", message);
+        mailbox.append(&sender(), &publish).await.unwrap();
+    }
+
+    async fn next_body(subscriber: &Subscriber) -> Option<String> {
+        subscriber.next().await.map(|email| email.body)
+    }
+
+    #[tokio::test]
+    async fn subscriber_receives_emails_of_its_channels() {
+        let (mailbox, subscriber) = mailbox_with_subscriber(SlowSubscriberPolicy::DropOldest).await;
+        mailbox.append(&sender(), &"misc".into()).await.unwrap();
+        publish(&mailbox, "a").await;
+        let urgent = Publish {
+            priority: 5,
+            ..publish_to("general", "urgent")
+        };
+        mailbox.append(&sender(), &urgent).await.unwrap();
+        assert_eq!(next_body(&subscriber).await.as_deref(), Some("urgent"));
+        assert_eq!(next_body(&subscriber).await.as_deref(), Some("a"));
+
+        assert_eq!(
+            mailbox.subscribe(&subscriber, "unknown").await,
+            Err(Error::UnknownChannel("unknown".to_owned()))
+        );
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_drops_buffered_emails() {
+        let (mailbox, subscriber) = mailbox_with_subscriber(SlowSubscriberPolicy::DropOldest).await;
+        for message in &["a", "b", "c"] {
+            publish(&mailbox, message).await;
+        }
+        let stats = mailbox.subscriber_stats().await;
+        assert_eq!(
+            stats,
+            vec![SubscriberStats {
+                id: subscriber.id,
+                channels: vec!["general".to_owned()],
+                buffered: 2,
+                dropped: 1,
+                lagging: true,
+            }]
+        );
+        assert_eq!(next_body(&subscriber).await.as_deref(), Some("b"));
+    }
+
+    #[tokio::test]
+    async fn disconnect_removes_slow_subscriber() {
+        let (mailbox, subscriber) = mailbox_with_subscriber(SlowSubscriberPolicy::Disconnect).await;
+        for message in &["a", "b", "c"] {
+            publish(&mailbox, message).await;
+        }
+        assert_eq!(next_body(&subscriber).await, None);
+        assert_eq!(mailbox.subscriber_stats().await, vec![]);
+        assert_eq!(mailbox.disconnected_subscribers().await, 1);
+        assert_eq!(&*mailbox.list_emails().await, "c;b;a;");
+    }
+
+    #[tokio::test]
+    async fn backpressure_blocks_publishers_until_subscriber_catches_up() {
+        let (mailbox, subscriber) =
+            mailbox_with_subscriber(SlowSubscriberPolicy::Backpressure).await;
+        publish(&mailbox, "a").await;
+        publish(&mailbox, "b").await;
+        let publisher = {
+            let mailbox = mailbox.clone();
+            tokio::spawn(async move { publish(&mailbox, "c").await })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(&*mailbox.list_emails().await, "b;a;");
+
+        assert_eq!(next_body(&subscriber).await.as_deref(), Some("a"));
+        publisher.await.unwrap();
+        assert_eq!(&*mailbox.list_emails().await, "c;b;a;");
+        assert_eq!(next_body(&subscriber).await.as_deref(), Some("b"));
+        assert_eq!(next_body(&subscriber).await.as_deref(), Some("c"));
+    }
+
+    #[tokio::test]
+    async fn unsubscribed_subscriber_receives_nothing() {
+        let (mailbox, subscriber) = mailbox_with_subscriber(SlowSubscriberPolicy::DropOldest).await;
+        mailbox.unsubscribe(&subscriber).await;
+        publish(&mailbox, "a").await;
+        assert_eq!(mailbox.subscriber_stats().await, vec![]);
+        let next = tokio::time::timeout(Duration::from_millis(50), subscriber.next()).await;
+        assert!(next.is_err());
+    }
+
+    #[tokio::test]
+    async fn backpressure_does_not_wait_for_the_subscriber_of_the_publisher() {
+        let (mailbox, subscriber) =
+            mailbox_with_subscriber(SlowSubscriberPolicy::Backpressure).await;
+        for message in &["a", "b", "c"] {
+            let (sender, publish) = (sender(), publish_to("general", message));
+            let append = mailbox.append_from(&subscriber, &sender, &publish);
+            let appended = tokio::time::timeout(Duration::from_secs(1), append).await;
+            assert!(appended.unwrap().is_ok());
+        }
+        assert_eq!(next_body(&subscriber).await.as_deref(), Some("a"));
+        assert_eq!(mailbox.disconnected_subscribers().await, 0);
+    }
+
+    #[tokio::test]
+    async fn backpressure_disconnects_subscriber_after_the_timeout() {
+        let mailbox = VecDequeMailbox::new(10, EvictionPolicy::DropOldest)
+            .with_subscribers(1, SlowSubscriberPolicy::Backpressure)
+            .with_backpressure_timeout(Duration::from_millis(50));
+        mailbox.create_channel("general").await.unwrap();
+        let subscriber = mailbox.subscriber().await;
+        mailbox.subscribe(&subscriber, "general").await.unwrap();
+        let sender = sender();
+        mailbox
+            .append(&sender, &publish_to("general", "a"))
+            .await
+            .unwrap();
+
+        let publish = publish_to("general", "b");
+        let append = mailbox.append(&sender, &publish);
+        let appended = tokio::time::timeout(Duration::from_secs(1), append).await;
+        assert_eq!(appended.unwrap(), Ok(Some(2)));
+        assert_eq!(mailbox.disconnected_subscribers().await, 1);
+        assert_eq!(mailbox.subscriber_stats().await, vec![]);
+        assert_eq!(next_body(&subscriber).await, None);
+    }
+
+    #[tokio::test]
+    async fn backpressure_gives_each_subscriber_its_own_timeout() {
+        let mailbox = Arc::new(
+            VecDequeMailbox::new(10, EvictionPolicy::DropOldest)
+                .with_subscribers(1, SlowSubscriberPolicy::Backpressure)
+                .with_backpressure_timeout(Duration::from_millis(300)),
+        );
+        mailbox.create_channel("general").await.unwrap();
+        mailbox.create_channel("other").await.unwrap();
+        let (first, second) = (mailbox.subscriber().await, mailbox.subscriber().await);
+        mailbox.subscribe(&first, "general").await.unwrap();
+        mailbox.subscribe(&second, "general").await.unwrap();
+        mailbox.subscribe(&second, "other").await.unwrap();
+        publish(&mailbox, "a").await;
+        next_body(&second).await;
+        let publisher = {
+            let mailbox = mailbox.clone();
+            tokio::spawn(async move { publish(&mailbox, "b").await })
+        };
+
+        // the second subscriber fills up while the publisher waits for the first one
+        let other = publish_to("other", "c");
+        mailbox.append(&sender(), &other).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(next_body(&first).await.as_deref(), Some("a"));
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(mailbox.disconnected_subscribers().await, 0);
+        assert!(!publisher.is_finished());
+
+        assert_eq!(next_body(&second).await.as_deref(), Some("c"));
+        publisher.await.unwrap();
+        assert_eq!(mailbox.disconnected_subscribers().await, 0);
+    }
+
+    #[tokio::test]
+    async fn backpressure_timeout_beyond_the_representable_time_waits_for_the_subscriber() {
+        let mailbox = Arc::new(
+            VecDequeMailbox::new(10, EvictionPolicy::DropOldest)
+                .with_subscribers(2, SlowSubscriberPolicy::Backpressure)
+                .with_backpressure_timeout(Duration::MAX),
+        );
+        mailbox.create_channel("general").await.unwrap();
+        let subscriber = mailbox.subscriber().await;
+        mailbox.subscribe(&subscriber, "general").await.unwrap();
+        publish(&mailbox, "a").await;
+        publish(&mailbox, "b").await;
+        let publisher = {
+            let mailbox = mailbox.clone();
+            tokio::spawn(async move { publish(&mailbox, "c").await })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(next_body(&subscriber).await.as_deref(), Some("a"));
+        publisher.await.unwrap();
+        assert_eq!(mailbox.disconnected_subscribers().await, 0);
+    }
+
+    #[tokio::test]
+    async fn subscriber_keeps_receiving_after_a_panic_while_locked() {
+        let (mailbox, subscriber) = mailbox_with_subscriber(SlowSubscriberPolicy::DropOldest).await;
+        let panicking = subscriber.clone();
+        let result = std::thread::spawn(move || {
+            let _state = panicking.state();
+            panic!("bug while the lock is held");
+        })
+        .join();
+        assert!(result.is_err());
+        assert!(subscriber.state.is_poisoned());
+
+        publish(&mailbox, "a").await;
+        assert_eq!(next_body(&subscriber).await.as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn parse_slow_subscriber_policy() {
+        assert_eq!("drop-oldest".parse(), Ok(SlowSubscriberPolicy::DropOldest));
+        assert_eq!("disconnect".parse(), Ok(SlowSubscriberPolicy::Disconnect));
+        assert_eq!(
+            "backpressure".parse(),
+            Ok(SlowSubscriberPolicy::Backpressure)
+        );
+        assert!("block".parse::<SlowSubscriberPolicy>().is_err());
+    }
+}
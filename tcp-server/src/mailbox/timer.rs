@@ -0,0 +1,139 @@
+use std::collections::BTreeMap;
+use std::future::{self, Future};
+use std::pin::{pin, Pin};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex, MutexGuard, OnceLock, PoisonError};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::Instant;
+
+/// Wakes up waiting tasks at their deadline. One thread serves the deadlines of all waits,
+/// so the mailbox times out waits without an async runtime and without a thread per wait.
+#[derive(Default)]
+struct Timer {
+    /// Wakers by deadline and id of the [Sleep]
+    wakers: Mutex<BTreeMap<(Instant, u64), Waker>>,
+    /// Wakes up the thread when an earlier deadline is added
+    added: Condvar,
+    next_id: AtomicU64,
+}
+
+/// Completes at the deadline, see [wait_until]
+struct Sleep {
+    deadline: Instant,
+    id: u64,
+}
+
+static TIMER: OnceLock<Timer> = OnceLock::new();
+
+/// Waits for the future, but not beyond the deadline.
+/// Returns `None` if the deadline passed first.
+pub(super) async fn wait_until<F: Future>(deadline: Instant, future: F) -> Option<F::Output> {
+    let mut future = pin!(future);
+    let mut sleep = Sleep {
+        deadline,
+        id: timer().next_id.fetch_add(1, Ordering::Relaxed),
+    };
+    future::poll_fn(|cx| match future.as_mut().poll(cx) {
+        Poll::Ready(output) => Poll::Ready(Some(output)),
+        Poll::Pending => Pin::new(&mut sleep).poll(cx).map(|()| None),
+    })
+    .await
+}
+
+/// The timer, its thread is started on first use
+fn timer() -> &'static Timer {
+    let mut started = false;
+    let timer = TIMER.get_or_init(|| {
+        started = true;
+        Timer::default()
+    });
+    if started {
+        thread::Builder::new()
+            .name("mailbox-timer".to_owned())
+            .spawn(move || timer.run())
+            .expect("can spawn the timer thread");
+    }
+    timer
+}
+
+impl Timer {
+    fn wakers(&self) -> MutexGuard<'_, BTreeMap<(Instant, u64), Waker>> {
+        self.wakers.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Wakes up every task whose deadline passed, then sleeps until the next deadline
+    fn run(&self) {
+        let mut wakers = self.wakers();
+        loop {
+            let now = Instant::now();
+            while let Some(entry) = wakers.first_entry() {
+                if entry.key().0 > now {
+                    break;
+                }
+                entry.remove().wake();
+            }
+            wakers = match wakers.keys().next() {
+                Some((deadline, _)) => {
+                    let timeout = deadline.saturating_duration_since(now);
+                    let waited = self.added.wait_timeout(wakers, timeout);
+                    waited.unwrap_or_else(PoisonError::into_inner).0
+                }
+                None => self
+                    .added
+                    .wait(wakers)
+                    .unwrap_or_else(PoisonError::into_inner),
+            };
+        }
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if Instant::now() >= self.deadline {
+            return Poll::Ready(());
+        }
+        let timer = timer();
+        let mut wakers = timer.wakers();
+        let key = (self.deadline, self.id);
+        let earliest = wakers.keys().next().is_none_or(|first| key < *first);
+        wakers.insert(key, cx.waker().clone());
+        if earliest {
+            timer.added.notify_one();
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        timer().wakers().remove(&(self.deadline, self.id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn wait_until_returns_the_output_before_the_deadline() {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        assert_eq!(wait_until(deadline, async { 42 }).await, Some(42));
+    }
+
+    #[tokio::test]
+    async fn wait_until_gives_up_at_the_deadline() {
+        let start = Instant::now();
+        let deadlines = [100, 50].map(|millis| start + Duration::from_millis(millis));
+        let waits = deadlines.map(|deadline| wait_until(deadline, future::pending::<()>()));
+        let [later, earlier] = waits;
+        assert_eq!(earlier.await, None);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(later.await, None);
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+}
@@ -1,119 +1,66 @@
-use std::collections::VecDeque;
 use std::io;
 use std::sync::Arc;
 use std::time::Duration;
 
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::Mutex;
-use tokio::task::JoinHandle;
-use tokio::time;
+use clap::Parser;
 
-use redisish::{Command, parse};
+#[cfg(unix)]
+use tcp_server::activation;
+use tcp_server::mailbox::VecDequeMailbox;
+use tcp_server::{logging, tls, Server, ServerBuilder};
 
-struct VecDequeMailbox {
-    data: Mutex<VecDeque<String>>,
-}
-
-impl VecDequeMailbox {
-    async fn append(&self, email: &str) {
-        let mut result = self.data.lock().await;
-        result.push_front(email.to_owned());
-    }
+use crate::config::Config;
 
-    /// Returns a list of emails as a string, separated by `;`
-    async fn list_emails(&self) -> String {
-        self.data
-            .lock()
-            .await
-            .iter()
-            .fold(String::new(), |acc, next| acc + next + ";")
-    }
-}
-
-impl VecDequeMailbox {
-    // TODO how to return Box<dyn Mailbox>?
-    fn new() -> Box<VecDequeMailbox> {
-        Box::new(VecDequeMailbox {
-            data: Mutex::new(VecDeque::new()),
-        })
-    }
-}
+mod config;
 
 /// General questions:
 /// * how to work with `dyn Mailbox ` in multithreaded environment?
-#[tokio::main]
-async fn main() -> io::Result<()> {
-    let (res1, res2) = tokio::join!(spawn_monitoring_thread(), spawn_tcp_listener());
-    res1?;
-    res2?;
-    Ok(())
-}
-
-/// Spawns TcpListener thread, connects to 127.0.0.1:8080 and spawns thread per connection
-fn spawn_tcp_listener() -> JoinHandle<()> {
-    tokio::spawn(async {
-        let mailbox = Arc::new(VecDequeMailbox::new());
-        let listener = TcpListener::bind("127.0.0.1:8080").await.unwrap();
-
-        loop {
-            let next = listener.accept().await.unwrap();
-            let mailbox = mailbox.clone();
-            tokio::spawn(async {
-                handle_client(BufReader::new(next.0), mailbox).await.unwrap();
-            });
-        }
-    })
+fn main() -> io::Result<()> {
+    let config = Config::parse();
+    let server = Server::builder();
+    // Safety: the runtime and its threads do not exist yet
+    #[cfg(unix)]
+    let server = server.activated(unsafe { activation::listeners()? });
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(serve(config, server))
 }
 
-/// TODO how can I pass the mailbox, preferably just mailbox: Mailbox or at least mailbox: Arc<Box<dyn Mailbox>>?
-async fn handle_client(
-    mut tcp_stream: BufReader<TcpStream>,
-    mailbox: Arc<Box<VecDequeMailbox>>,
-) -> Result<(), io::Error> {
-    loop {
-        let mut str = String::new();
-        let ret = tcp_stream.read_line(&mut str).await;
-        if let Ok(0) = ret {
-            break;
-        }
-        let result = parse(&str);
-        match result {
-            Ok(Command::Publish(payload)) => {
-                println!("Appending email: {}", payload);
-                mailbox.append(payload.as_ref()).await;
-            }
-            Ok(Command::Retrieve) => {
-                tcp_stream
-                    .write_all(mailbox.list_emails().await.as_ref())
-                    .await?;
-                tcp_stream.write_all("\n".as_ref()).await?;
-            }
-            Err(err) => {
-                println!("Client error: {}", err);
-                break;
-            }
-        }
+async fn serve(config: Config, server: ServerBuilder) -> io::Result<()> {
+    logging::init(&config.log_level, config.log_format)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    let mailbox = Arc::new(
+        VecDequeMailbox::new(config.capacity as usize, config.eviction)
+            .with_visibility_timeout(Duration::from_secs(config.visibility_timeout))
+            .with_max_failures(config.max_failures)
+            .with_max_groups(config.max_groups as usize)
+            .with_deduplication(
+                Duration::from_secs(config.dedup_window),
+                config.dedup_capacity,
+            )
+            .with_subscribers(config.subscriber_buffer as usize, config.slow_subscriber)
+            .with_backpressure_timeout(Duration::from_millis(config.backpressure_timeout)),
+    );
+    let mut server = server
+        .bind(config.bind)
+        .mailbox(mailbox)
+        .sweep_interval(Duration::from_millis(config.sweep_interval));
+    if let (Some(cert), Some(key)) = (&config.tls_cert, &config.tls_key) {
+        server = server.tls(tls::acceptor(cert, key)?);
     }
-
-    Ok(())
-}
-
-fn spawn_monitoring_thread() -> JoinHandle<()> {
-    tokio::spawn(async {
-        // TODO replace with retry
-        time::sleep(Duration::from_millis(1000)).await;
-        let client = TcpStream::connect("127.0.0.1:8080").await.unwrap();
-        let mut client = BufReader::new(client);
-        loop {
-            client
-                .write_all(Command::Retrieve.as_string().as_ref())
-                .await
-                .unwrap();
-            let mut str = String::new();
-            client.read_line(&mut str).await.unwrap();
-            println!("Mailbox content: {}", str.trim_end());
-            time::sleep(Duration::from_millis(5000)).await;
-        }
-    })
+    #[cfg(unix)]
+    if let Some(path) = config.unix_socket {
+        server = server.unix_socket(path, config.unix_socket_mode);
+    }
+    if let Some(http_addr) = config.http_addr {
+        server = server.http_addr(http_addr);
+    }
+    if let Some(metrics_addr) = config.metrics_addr {
+        server = server.metrics_addr(metrics_addr);
+    }
+    if let Some(info_interval) = config.info_interval {
+        server = server.info_interval(Duration::from_secs(info_interval));
+    }
+    server.start().await?.wait().await
 }
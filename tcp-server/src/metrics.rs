@@ -0,0 +1,217 @@
+use std::fmt::Write;
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+
+use crate::mailbox::VecDequeMailbox;
+use crate::server::shutdown_signal;
+use crate::stats::{Stats, LATENCY_BUCKETS};
+
+/// Content type of the Prometheus text exposition format
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Serves the metrics for Prometheus at `http://<addr>/metrics` until the server shuts down
+pub async fn serve(
+    listener: TcpListener,
+    stats: Arc<Stats>,
+    mailbox: Arc<VecDequeMailbox>,
+    shutdown: watch::Receiver<bool>,
+) {
+    let app = Router::new()
+        .route("/metrics", get(metrics))
+        .with_state((stats, mailbox));
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal(shutdown))
+        .await
+        .unwrap();
+}
+
+async fn metrics(
+    State((stats, mailbox)): State<(Arc<Stats>, Arc<VecDequeMailbox>)>,
+) -> impl IntoResponse {
+    let body = render(&stats, &mailbox).await;
+    ([(header::CONTENT_TYPE, CONTENT_TYPE)], body)
+}
+
+/// Renders the metrics in the Prometheus text exposition format
+pub async fn render(stats: &Stats, mailbox: &VecDequeMailbox) -> String {
+    let mut out = String::new();
+    // samples are the suffix of the name including the labels and the value
+    let mut metric = |name: &str, kind: &str, help: &str, samples: Vec<(String, String)>| {
+        writeln!(out, "# HELP {} {}", name, help).unwrap();
+        writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+        for (labels, value) in samples {
+            writeln!(out, "{}{} {}", name, labels, value).unwrap();
+        }
+    };
+    let single = |value: String| vec![(String::new(), value)];
+
+    metric(
+        "redisish_uptime_seconds",
+        "gauge",
+        "Seconds since the server started.",
+        single(stats.uptime().as_secs().to_string()),
+    );
+    metric(
+        "redisish_connected_clients",
+        "gauge",
+        "Number of connected clients.",
+        single(stats.connected_clients().to_string()),
+    );
+    metric(
+        "redisish_connections_total",
+        "counter",
+        "Number of accepted connections.",
+        single(stats.connections().to_string()),
+    );
+
+    let commands = stats.commands();
+    metric(
+        "redisish_commands_total",
+        "counter",
+        "Number of processed commands by verb.",
+        commands
+            .iter()
+            .map(|(verb, it)| {
+                (
+                    format!("{{verb=\"{}\"}}", escape(verb)),
+                    it.count.to_string(),
+                )
+            })
+            .collect(),
+    );
+    let mut samples = vec![];
+    for (verb, it) in &commands {
+        let verb = escape(verb);
+        let mut cumulative = 0;
+        for (le, count) in LATENCY_BUCKETS.iter().zip(it.latency_buckets) {
+            cumulative += count;
+            let bucket = format!("_bucket{{verb=\"{}\",le=\"{}\"}}", verb, le);
+            samples.push((bucket, cumulative.to_string()));
+        }
+        let bucket = format!("_bucket{{verb=\"{}\",le=\"+Inf\"}}", verb);
+        samples.push((bucket, it.count.to_string()));
+        let labels = format!("{{verb=\"{}\"}}", verb);
+        let sum = it.latency_sum.as_secs_f64().to_string();
+        samples.push((format!("_sum{}", labels), sum));
+        samples.push((format!("_count{}", labels), it.count.to_string()));
+    }
+    metric(
+        "redisish_command_duration_seconds",
+        "histogram",
+        "Time to execute a command by verb.",
+        samples,
+    );
+
+    metric(
+        "redisish_parse_errors_total",
+        "counter",
+        "Number of unparsable commands by error.",
+        stats
+            .parse_errors()
+            .iter()
+            .map(|(error, count)| {
+                (
+                    format!("{{error=\"{}\"}}", escape(error)),
+                    count.to_string(),
+                )
+            })
+            .collect(),
+    );
+    metric(
+        "redisish_mailbox_messages",
+        "gauge",
+        "Number of stored messages by channel.",
+        mailbox
+            .list_channels()
+            .await
+            .iter()
+            .map(|(channel, count)| {
+                let labels = format!("{{channel=\"{}\"}}", escape(channel));
+                (labels, count.to_string())
+            })
+            .collect(),
+    );
+    metric(
+        "redisish_mailbox_bytes",
+        "gauge",
+        "Size of the stored message bodies.",
+        single(mailbox.stored_bytes().await.to_string()),
+    );
+    metric(
+        "redisish_evicted_messages_total",
+        "counter",
+        "Number of messages dropped by the eviction policy.",
+        single(mailbox.evicted().await.to_string()),
+    );
+    metric(
+        "redisish_expired_messages_total",
+        "counter",
+        "Number of messages dropped because their time to live ran out.",
+        single(mailbox.expired().await.to_string()),
+    );
+    out
+}
+
+/// Escapes `\`, `"` and line feeds in a label value, which would otherwise end the value or the line
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::mailbox::tests::sender;
+    use crate::mailbox::EvictionPolicy;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn renders_prometheus_text_format() {
+        let mailbox = VecDequeMailbox::new(1, EvictionPolicy::DropOldest);
+        mailbox.append(&sender(), &"a".into()).await.unwrap();
+        mailbox.append(&sender(), &"b".into()).await.unwrap();
+        let stats = Stats::new();
+        stats.client_connected();
+        stats.command("PUBLISH", Duration::from_micros(300));
+        stats.command("PUBLISH", Duration::from_millis(20));
+
+        let metrics = render(&stats, &mailbox).await;
+        for line in [
+            "# TYPE redisish_connected_clients gauge",
+            "redisish_connected_clients 1",
+            "redisish_connections_total 1",
+            "redisish_commands_total{verb=\"PUBLISH\"} 2",
+            "redisish_command_duration_seconds_bucket{verb=\"PUBLISH\",le=\"0.00025\"} 0",
+            "redisish_command_duration_seconds_bucket{verb=\"PUBLISH\",le=\"0.0005\"} 1",
+            "redisish_command_duration_seconds_bucket{verb=\"PUBLISH\",le=\"0.05\"} 2",
+            "redisish_command_duration_seconds_bucket{verb=\"PUBLISH\",le=\"+Inf\"} 2",
+            "redisish_command_duration_seconds_count{verb=\"PUBLISH\"} 2",
+            "redisish_mailbox_messages{channel=\"misc\"} 1",
+            "redisish_mailbox_bytes 1",
+            "redisish_evicted_messages_total 1",
+        ] {
+            assert!(metrics.lines().any(|it| it == line), "missing {}", line);
+        }
+    }
+
+    #[tokio::test]
+    async fn escapes_label_values() {
+        let mailbox = VecDequeMailbox::new(1, EvictionPolicy::DropOldest);
+        mailbox.create_channel("a\\b\"c\nd").await.unwrap();
+
+        let metrics = render(&Stats::new(), &mailbox).await;
+        let line = r#"redisish_mailbox_messages{channel="a\\b\"c\nd"} 0"#;
+        assert!(metrics.lines().any(|it| it == line), "missing {}", line);
+    }
+}
@@ -0,0 +1,400 @@
+use std::future;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite, BufReader};
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tokio::time;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, info, warn, Instrument};
+
+#[cfg(unix)]
+use crate::activation::Activated;
+use crate::connection::{connection_span, handle_client};
+use crate::mailbox::{EvictionPolicy, PeerAddr, Sender, VecDequeMailbox};
+use crate::stats::Stats;
+#[cfg(unix)]
+use crate::unix;
+use crate::{gateway, metrics};
+
+/// How long a listener pauses after accepting a connection failed
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// The redisish server with its listeners and background tasks, see [Server::builder]
+pub struct Server;
+
+impl Server {
+    /// Starts configuring a server which listens on `127.0.0.1:8080` and has an empty mailbox
+    /// with room for 10 000 emails
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder {
+            bind: SocketAddr::from(([127, 0, 0, 1], 8080)),
+            mailbox: None,
+            tls: None,
+            #[cfg(unix)]
+            unix_socket: None,
+            #[cfg(unix)]
+            activated: vec![],
+            http_addr: None,
+            metrics_addr: None,
+            info_interval: None,
+            sweep_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Configures the listeners of a [Server], nothing is bound until [ServerBuilder::start]
+pub struct ServerBuilder {
+    bind: SocketAddr,
+    mailbox: Option<Arc<VecDequeMailbox>>,
+    tls: Option<TlsAcceptor>,
+    #[cfg(unix)]
+    unix_socket: Option<(PathBuf, Option<u32>)>,
+    #[cfg(unix)]
+    activated: Vec<Activated>,
+    http_addr: Option<SocketAddr>,
+    metrics_addr: Option<SocketAddr>,
+    info_interval: Option<Duration>,
+    sweep_interval: Duration,
+}
+
+impl ServerBuilder {
+    /// Address of the redisish listener, port 0 picks a free port, see [ServerHandle::local_addr]
+    pub fn bind(mut self, addr: SocketAddr) -> Self {
+        self.bind = addr;
+        self
+    }
+
+    /// The mailbox shared by all listeners, it may be shared with other servers as well
+    pub fn mailbox(mut self, mailbox: Arc<VecDequeMailbox>) -> Self {
+        self.mailbox = Some(mailbox);
+        self
+    }
+
+    /// Encrypts the connections of the redisish TCP listener, see [crate::tls::acceptor]
+    pub fn tls(mut self, tls: TlsAcceptor) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Also listens on a Unix domain socket, `mode` are its permissions, e.g. `0o660`
+    #[cfg(unix)]
+    pub fn unix_socket(mut self, path: impl Into<PathBuf>, mode: Option<u32>) -> Self {
+        self.unix_socket = Some((path.into(), mode));
+        self
+    }
+
+    /// Serves the sockets passed by systemd instead of binding the redisish TCP listener and
+    /// the Unix domain socket, see [crate::activation::listeners]. No sockets change nothing.
+    #[cfg(unix)]
+    pub fn activated(mut self, listeners: Vec<Activated>) -> Self {
+        self.activated = listeners;
+        self
+    }
+
+    /// Serves the HTTP/JSON and WebSocket gateway on the address
+    pub fn http_addr(mut self, addr: SocketAddr) -> Self {
+        self.http_addr = Some(addr);
+        self
+    }
+
+    /// Serves the Prometheus metrics at `http://<addr>/metrics`
+    pub fn metrics_addr(mut self, addr: SocketAddr) -> Self {
+        self.metrics_addr = Some(addr);
+        self
+    }
+
+    /// Logs the INFO statistics periodically
+    pub fn info_interval(mut self, interval: Duration) -> Self {
+        self.info_interval = Some(interval);
+        self
+    }
+
+    /// How often emails whose time to live ran out are dropped, even if nobody reads the mailbox
+    pub fn sweep_interval(mut self, interval: Duration) -> Self {
+        self.sweep_interval = interval;
+        self
+    }
+
+    /// Binds all listeners and spawns the tasks serving them on the current tokio runtime
+    pub async fn start(self) -> io::Result<ServerHandle> {
+        let mailbox = self
+            .mailbox
+            .unwrap_or_else(|| Arc::new(VecDequeMailbox::new(10_000, EvictionPolicy::DropOldest)));
+        let stats = Arc::new(Stats::new());
+        let (shutdown, shutdown_signal) = watch::channel(false);
+        let mut handle = ServerHandle {
+            local_addr: None,
+            http_addr: None,
+            metrics_addr: None,
+            #[cfg(unix)]
+            unix_socket: None,
+            shutdown,
+            tasks: JoinSet::new(),
+            servers: JoinSet::new(),
+        };
+
+        let mut tcp_listeners = vec![];
+        #[cfg(unix)]
+        let mut unix_listeners = vec![];
+        #[cfg(unix)]
+        let activated = !self.activated.is_empty();
+        #[cfg(not(unix))]
+        let activated = false;
+        if !activated {
+            tcp_listeners.push(TcpListener::bind(self.bind).await?);
+            #[cfg(unix)]
+            if let Some((path, mode)) = self.unix_socket {
+                unix_listeners.push(unix::bind(&path, mode)?);
+                info!(path = %path.display(), "Listening");
+                handle.unix_socket = Some(path);
+            }
+        }
+        #[cfg(unix)]
+        for listener in self.activated {
+            match listener {
+                Activated::Tcp(listener) => tcp_listeners.push(TcpListener::from_std(listener)?),
+                Activated::Unix(listener) => {
+                    info!("Listening on activated Unix domain socket");
+                    unix_listeners.push(UnixListener::from_std(listener)?);
+                }
+            }
+        }
+        for listener in tcp_listeners {
+            let addr = listener.local_addr()?;
+            info!(%addr, tls = self.tls.is_some(), "Listening");
+            handle.local_addr.get_or_insert(addr);
+            let (tls, mailbox, stats) = (self.tls.clone(), mailbox.clone(), stats.clone());
+            handle
+                .tasks
+                .spawn(serve_tcp_listener(listener, tls, mailbox, stats));
+        }
+        #[cfg(unix)]
+        for listener in unix_listeners {
+            let (mailbox, stats) = (mailbox.clone(), stats.clone());
+            handle
+                .tasks
+                .spawn(serve_unix_listener(listener, mailbox, stats));
+        }
+
+        if let Some(addr) = self.http_addr {
+            let listener = TcpListener::bind(addr).await?;
+            handle.http_addr = Some(listener.local_addr()?);
+            info!(addr = %listener.local_addr()?, "HTTP gateway listening");
+            handle.servers.spawn(gateway::serve(
+                listener,
+                mailbox.clone(),
+                stats.clone(),
+                shutdown_signal.clone(),
+            ));
+        }
+        if let Some(addr) = self.metrics_addr {
+            let listener = TcpListener::bind(addr).await?;
+            handle.metrics_addr = Some(listener.local_addr()?);
+            handle.servers.spawn(metrics::serve(
+                listener,
+                stats.clone(),
+                mailbox.clone(),
+                shutdown_signal.clone(),
+            ));
+        }
+        if let Some(interval) = self.info_interval {
+            let (mailbox, stats) = (mailbox.clone(), stats.clone());
+            handle.tasks.spawn(log_info(stats, mailbox, interval));
+        }
+        handle.tasks.spawn(sweep(mailbox, self.sweep_interval));
+        Ok(handle)
+    }
+}
+
+/// A running [Server]. Dropping the handle aborts all its tasks, [ServerHandle::shutdown]
+/// lets HTTP requests in progress finish.
+pub struct ServerHandle {
+    local_addr: Option<SocketAddr>,
+    http_addr: Option<SocketAddr>,
+    metrics_addr: Option<SocketAddr>,
+    /// Unix domain socket bound by the server, removed on shutdown
+    #[cfg(unix)]
+    unix_socket: Option<PathBuf>,
+    shutdown: watch::Sender<bool>,
+    /// Listeners and background tasks, aborted on shutdown together with their connections
+    tasks: JoinSet<()>,
+    /// HTTP servers which shut down gracefully
+    servers: JoinSet<()>,
+}
+
+impl ServerHandle {
+    /// Address of the redisish TCP listener,
+    /// `None` if the server only serves Unix domain sockets passed by systemd
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    /// Address of the HTTP/JSON and WebSocket gateway if enabled
+    pub fn http_addr(&self) -> Option<SocketAddr> {
+        self.http_addr
+    }
+
+    /// Address of the Prometheus metrics listener if enabled
+    pub fn metrics_addr(&self) -> Option<SocketAddr> {
+        self.metrics_addr
+    }
+
+    /// Runs until a task of the server fails
+    pub async fn wait(mut self) -> io::Result<()> {
+        while let Some(result) = self.tasks.join_next().await {
+            result?;
+        }
+        Ok(())
+    }
+
+    /// Stops listening, disconnects all clients and waits until the tasks of the server ended
+    pub async fn shutdown(mut self) {
+        let _ = self.shutdown.send(true);
+        self.tasks.shutdown().await;
+        while self.servers.join_next().await.is_some() {}
+        #[cfg(unix)]
+        if let Some(path) = &self.unix_socket {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Resolves once the server shuts down or its handle is dropped
+pub(crate) async fn shutdown_signal(mut shutdown: watch::Receiver<bool>) {
+    let _ = shutdown.wait_for(|stopped| *stopped).await;
+}
+
+/// Accepts connections and serves each in its own task.
+/// Connections are TLS encrypted if there is an acceptor.
+async fn serve_tcp_listener(
+    listener: TcpListener,
+    tls: Option<TlsAcceptor>,
+    mailbox: Arc<VecDequeMailbox>,
+    stats: Arc<Stats>,
+) {
+    // aborted together with the listener
+    let mut connections = JoinSet::new();
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            Some(_) = connections.join_next() => continue,
+        };
+        let (tcp_stream, peer_addr) = match accepted {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                accept_failed(err).await;
+                continue;
+            }
+        };
+        let (peer_addr, mailbox, stats) = (peer_addr.into(), mailbox.clone(), stats.clone());
+        match &tls {
+            None => {
+                let tcp_stream = future::ready(Ok(tcp_stream));
+                connections.spawn(serve_client(tcp_stream, peer_addr, mailbox, stats))
+            }
+            Some(tls) => {
+                let tls_stream = tls.accept(tcp_stream);
+                connections.spawn(serve_client(tls_stream, peer_addr, mailbox, stats))
+            }
+        };
+    }
+}
+
+/// Accepts connections of the Unix domain socket listener and serves each in its own task
+#[cfg(unix)]
+async fn serve_unix_listener(
+    listener: UnixListener,
+    mailbox: Arc<VecDequeMailbox>,
+    stats: Arc<Stats>,
+) {
+    let mut connections = JoinSet::new();
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            Some(_) = connections.join_next() => continue,
+        };
+        let (unix_stream, _) = match accepted {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                accept_failed(err).await;
+                continue;
+            }
+        };
+        let unix_stream = future::ready(Ok(unix_stream));
+        let (mailbox, stats) = (mailbox.clone(), stats.clone());
+        connections.spawn(serve_client(unix_stream, PeerAddr::Unix, mailbox, stats));
+    }
+}
+
+/// Logs a failed accept, e.g. because the process ran out of file descriptors, and backs off
+/// briefly instead of retrying at once
+async fn accept_failed(err: io::Error) {
+    warn!(%err, "Accepting a connection failed");
+    time::sleep(ACCEPT_BACKOFF).await;
+}
+
+/// Serves the connection once it is established, e.g. after the TLS handshake
+fn serve_client<S>(
+    connect: impl Future<Output = io::Result<S>> + Send + 'static,
+    peer_addr: PeerAddr,
+    mailbox: Arc<VecDequeMailbox>,
+    stats: Arc<Stats>,
+) -> impl Future<Output = ()> + Send + 'static
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    let (connection_id, span) = connection_span(&peer_addr);
+    let sender = Sender {
+        peer_addr,
+        client_name: None,
+    };
+    async move {
+        info!("Client connected");
+        stats.client_connected();
+        let result = match connect.await {
+            Ok(stream) => {
+                let stream = BufReader::new(stream);
+                handle_client(stream, connection_id, sender, mailbox, &stats).await
+            }
+            Err(err) => Err(err),
+        };
+        stats.client_disconnected();
+        match result {
+            Ok(()) => info!("Client disconnected"),
+            Err(err) => warn!(%err, "Connection failed"),
+        }
+    }
+    .instrument(span)
+}
+
+/// Periodically prints the INFO statistics
+async fn log_info(stats: Arc<Stats>, mailbox: Arc<VecDequeMailbox>, interval: Duration) {
+    let mut interval = time::interval(interval);
+    loop {
+        interval.tick().await;
+        let info = stats.info(&mailbox).await.join(", ");
+        info!(%info, "Info");
+    }
+}
+
+/// Periodically drops emails whose time to live ran out, even if nobody reads the mailbox
+async fn sweep(mailbox: Arc<VecDequeMailbox>, interval: Duration) {
+    let mut interval = time::interval(interval);
+    loop {
+        interval.tick().await;
+        let expired = mailbox.sweep().await;
+        if expired > 0 {
+            debug!(expired, "Swept expired emails");
+        }
+    }
+}
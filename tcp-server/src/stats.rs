@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::clients::Clients;
+use crate::mailbox::VecDequeMailbox;
+
+/// Upper bounds of the command latency histogram buckets in seconds
+pub const LATENCY_BUCKETS: [f64; 10] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.05, 0.1, 1.0,
+];
+
+/// Number and latencies of the processed commands of a verb
+#[derive(Clone, Default, Debug)]
+pub struct CommandStats {
+    pub count: u64,
+    pub latency_sum: Duration,
+    /// Number of commands by latency bucket, commands slower than the last bucket are not counted
+    pub latency_buckets: [u64; LATENCY_BUCKETS.len()],
+}
+
+/// Server statistics reported by the INFO command and the metrics endpoint
+pub struct Stats {
+    started: Instant,
+    connected_clients: AtomicUsize,
+    connections: AtomicU64,
+    /// Processed commands by verb
+    commands: Mutex<BTreeMap<&'static str, CommandStats>>,
+    /// Parse errors by [redisish::Error] variant
+    parse_errors: Mutex<BTreeMap<&'static str, u64>>,
+    clients: Clients,
+}
+
+impl Stats {
+    pub fn new() -> Stats {
+        Stats {
+            started: Instant::now(),
+            connected_clients: AtomicUsize::new(0),
+            connections: AtomicU64::new(0),
+            commands: Mutex::new(BTreeMap::new()),
+            parse_errors: Mutex::new(BTreeMap::new()),
+            clients: Clients::default(),
+        }
+    }
+
+    pub fn client_connected(&self) {
+        self.connected_clients.fetch_add(1, Ordering::Relaxed);
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn client_disconnected(&self) {
+        self.connected_clients.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Counts a processed command and how long it took
+    pub fn command(&self, verb: &'static str, latency: Duration) {
+        let mut commands = lock(&self.commands);
+        let stats = commands.entry(verb).or_default();
+        stats.count += 1;
+        stats.latency_sum += latency;
+        let seconds = latency.as_secs_f64();
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|le| seconds <= *le) {
+            stats.latency_buckets[bucket] += 1;
+        }
+    }
+
+    pub fn parse_error(&self, err: &redisish::Error) {
+        *lock(&self.parse_errors).entry(err.name()).or_default() += 1;
+    }
+
+    /// The connected clients listed by CLIENT LIST
+    pub fn clients(&self) -> &Clients {
+        &self.clients
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn connected_clients(&self) -> usize {
+        self.connected_clients.load(Ordering::Relaxed)
+    }
+
+    /// Number of accepted connections since the start, reported by the metrics endpoint
+    #[cfg(feature = "async")]
+    pub fn connections(&self) -> u64 {
+        self.connections.load(Ordering::Relaxed)
+    }
+
+    pub fn commands(&self) -> BTreeMap<&'static str, CommandStats> {
+        lock(&self.commands).clone()
+    }
+
+    pub fn parse_errors(&self) -> BTreeMap<&'static str, u64> {
+        lock(&self.parse_errors).clone()
+    }
+
+    /// Statistics of the server and the mailbox as `<name> <value>` lines
+    pub async fn info(&self, mailbox: &VecDequeMailbox) -> Vec<String> {
+        let channels = mailbox.list_channels().await;
+        let subscribers = mailbox.subscriber_stats().await;
+        let mut info = vec![
+            format!("uptime_seconds {}", self.uptime().as_secs()),
+            format!("connected_clients {}", self.connected_clients()),
+            format!(
+                "stored_messages {}",
+                channels.iter().map(|(_, count)| count).sum::<u64>()
+            ),
+            format!("stored_bytes {}", mailbox.stored_bytes().await),
+            format!("evicted_messages {}", mailbox.evicted().await),
+            format!("expired_messages {}", mailbox.expired().await),
+            format!("subscribers {}", subscribers.len()),
+            format!(
+                "lagging_subscribers {}",
+                subscribers.iter().filter(|it| it.lagging).count()
+            ),
+            format!(
+                "disconnected_subscribers {}",
+                mailbox.disconnected_subscribers().await
+            ),
+        ];
+        for (channel, count) in channels {
+            info.push(format!("messages:{} {}", channel, count));
+        }
+        for (verb, stats) in self.commands() {
+            info.push(format!("commands:{} {}", verb, stats.count));
+        }
+        for (variant, count) in self.parse_errors() {
+            info.push(format!("parse_errors:{} {}", variant, count));
+        }
+        info
+    }
+}
+
+/// Locks the statistics, also if a panic poisoned the lock,
+/// so one failing connection does not fail INFO and the metrics for good
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use redisish::parse;
+
+    use crate::mailbox::tests::sender;
+    use crate::mailbox::EvictionPolicy;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn info_reports_server_and_mailbox_statistics() {
+        let mailbox = VecDequeMailbox::new(10, EvictionPolicy::DropOldest);
+        mailbox.append(&sender(), &"hello".into()).await.unwrap();
+        let stats = Stats::new();
+        stats.client_connected();
+        stats.client_connected();
+        stats.client_disconnected();
+        stats.command("PUBLISH", Duration::from_micros(50));
+        stats.command("PUBLISH", Duration::from_millis(2));
+        stats.command("INFO", Duration::from_secs(2));
+        stats.parse_error(&parse("PUBLISH hello").unwrap_err());
+
+        let info = stats.info(&mailbox).await;
+        assert!(info[0].starts_with("uptime_seconds "));
+        assert_eq!(
+            info[1..],
+            [
+                "connected_clients 1",
+                "stored_messages 1",
+                "stored_bytes 5",
+                "evicted_messages 0",
+                "expired_messages 0",
+                "subscribers 0",
+                "lagging_subscribers 0",
+                "disconnected_subscribers 0",
+                "messages:misc 1",
+                "commands:INFO 1",
+                "commands:PUBLISH 2",
+                "parse_errors:MissingNewline 1",
+            ]
+        );
+        let publish = &stats.commands()["PUBLISH"];
+        assert_eq!(publish.latency_sum, Duration::from_micros(2050));
+        assert_eq!(publish.latency_buckets, [1, 0, 0, 0, 1, 0, 0, 0, 0, 0]);
+        assert_eq!(stats.commands()["INFO"].latency_buckets, [0; 10]);
+    }
+
+    #[test]
+    fn stats_keep_counting_after_a_panic_while_locked() {
+        let stats = std::sync::Arc::new(Stats::new());
+        let panicking = stats.clone();
+        let result = std::thread::spawn(move || {
+            let _commands = lock(&panicking.commands);
+            panic!("bug while the lock is held");
+        })
+        .join();
+        assert!(result.is_err());
+        assert!(stats.commands.is_poisoned());
+
+        stats.command("PUBLISH", Duration::from_micros(50));
+        assert_eq!(stats.commands()["PUBLISH"].count, 1);
+    }
+}
@@ -0,0 +1,67 @@
+#![cfg(unix)]
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+use common::{connect, free_addr, Server};
+
+mod common;
+
+/// Starts the server like systemd does: the listeners are file descriptors 3 and 4,
+/// the shell sets `LISTEN_PID` to its own pid and `exec` keeps it for the server
+fn start_activated(tcp: &TcpListener, unix: &UnixListener, args: &[&str]) -> Server {
+    let (tcp, unix) = (tcp.as_raw_fd(), unix.as_raw_fd());
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg("LISTEN_PID=$$ exec \"$0\" \"$@\"")
+        .arg(env!("CARGO_BIN_EXE_tcp-server"))
+        .args(args)
+        .env("LISTEN_FDS", "2");
+    // Safety: only async-signal-safe functions are called between fork and exec
+    unsafe {
+        command.pre_exec(move || {
+            // move the sockets out of the way first, they may already use 3 or 4
+            let tcp = libc::fcntl(tcp, libc::F_DUPFD, 10);
+            let unix = libc::fcntl(unix, libc::F_DUPFD, 10);
+            if tcp < 0 || unix < 0 || libc::dup2(tcp, 3) < 0 || libc::dup2(unix, 4) < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    Server::spawn(command)
+}
+
+fn publish(stream: impl std::io::Read + Write, message: &str) -> String {
+    let mut reader = BufReader::new(stream);
+    reader
+        .get_mut()
+        .write_all(format!("PUBLISH {}\n", message).as_bytes())
+        .unwrap();
+    let mut reply = String::new();
+    reader.read_line(&mut reply).unwrap();
+    reply
+}
+
+#[test]
+fn serves_sockets_passed_by_the_service_manager() {
+    let dir = tempfile::tempdir().unwrap();
+    let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+    let unix = UnixListener::bind(dir.path().join("redisish.sock")).unwrap();
+    let bind = free_addr();
+    let _server = start_activated(&tcp, &unix, &["--bind", &bind.to_string()]);
+
+    let addr = tcp.local_addr().unwrap();
+    assert_eq!(publish(connect(addr), "tcp"), "OK 1\n");
+    let path = dir.path().join("redisish.sock");
+    assert_eq!(
+        publish(UnixStream::connect(path).unwrap(), "unix"),
+        "OK 2\n"
+    );
+    assert!(TcpStream::connect(bind).is_err());
+}
@@ -0,0 +1,26 @@
+use std::process::Command;
+
+use common::{connect, free_addr, Server};
+
+mod common;
+
+#[test]
+fn bench_reports_latencies_of_a_local_server() {
+    let bind = free_addr();
+    let _server = Server::start(&["--bind", &bind.to_string(), "--log-level", "warn"]);
+    drop(connect(bind));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_redisish-bench"))
+        .args(["--server", &bind.to_string(), "--connections", "2"])
+        .args(["--duration", "1", "--publish-ratio", "0.2"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let report = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<_> = report.lines().collect();
+    assert!(lines[0].starts_with("2 connections, 20% PUBLISH of 64 bytes, "));
+    for (line, name) in lines[1..].iter().zip(["PUBLISH", "RETRIEVE", "total"]) {
+        assert!(line.starts_with(name), "{}", report);
+        assert!(line.contains(" p999 "), "{}", report);
+    }
+}
@@ -0,0 +1,100 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use tcp_server::blocking;
+use tcp_server::mailbox::{EvictionPolicy, VecDequeMailbox};
+
+/// Serves the mailbox on an ephemeral port, the server thread ends with the test process
+fn start(mailbox: &Arc<VecDequeMailbox>) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mailbox = mailbox.clone();
+    thread::spawn(move || blocking::serve(listener, mailbox, Duration::from_millis(100)));
+    addr
+}
+
+fn send(stream: &mut BufReader<TcpStream>, command: &str) -> String {
+    stream.get_mut().write_all(command.as_bytes()).unwrap();
+    let mut reply = String::new();
+    stream.read_line(&mut reply).unwrap();
+    reply
+}
+
+#[test]
+fn blocking_server_shares_the_mailbox_and_pushes_subscribed_emails() {
+    let mailbox = Arc::new(VecDequeMailbox::new(10, EvictionPolicy::DropOldest));
+    let addr = start(&mailbox);
+    let mut subscriber = BufReader::new(TcpStream::connect(addr).unwrap());
+    let mut publisher = BufReader::new(TcpStream::connect(addr).unwrap());
+
+    assert_eq!(send(&mut subscriber, "CREATE news\n"), "OK\n");
+    assert_eq!(send(&mut subscriber, "SUBSCRIBE news\n"), "OK\n");
+    assert_eq!(send(&mut publisher, "PUBLISH hello\n"), "OK 1\n");
+    assert_eq!(send(&mut publisher, "PUBLISH TO news extra\n"), "OK 2\n");
+    assert_eq!(send(&mut publisher, "RETRIEVE\n"), "extra;hello;\n");
+    assert_eq!(&*blocking::block_on(mailbox.list_emails()), "extra;hello;");
+
+    let mut pushed = String::new();
+    subscriber.read_line(&mut pushed).unwrap();
+    assert!(pushed.starts_with("MESSAGE 2 "), "{}", pushed);
+    assert!(pushed.ends_with(" - news extra\n"), "{}", pushed);
+
+    assert_eq!(send(&mut subscriber, "CLIENT LIST\n"), "2\n");
+    // ordered by id, the subscriber connected first
+    let mut lines = [String::new(), String::new()];
+    for line in &mut lines {
+        subscriber.read_line(line).unwrap();
+    }
+    let id = lines[0].split(' ').next().unwrap();
+    let kill = format!("CLIENT KILL {}\n", id);
+    assert_eq!(send(&mut publisher, &kill), "OK\n");
+    let mut rest = String::new();
+    assert_eq!(subscriber.read_to_string(&mut rest).unwrap(), 0);
+}
+
+#[test]
+fn blocking_server_closes_the_connection_on_a_parse_error() {
+    let addr = start(&Arc::new(VecDequeMailbox::new(
+        10,
+        EvictionPolicy::DropOldest,
+    )));
+    let mut client = TcpStream::connect(addr).unwrap();
+    client.write_all(b"HELLO\n").unwrap();
+    let mut rest = String::new();
+    assert_eq!(client.read_to_string(&mut rest).unwrap(), 0);
+}
+
+#[test]
+#[cfg(unix)]
+fn blocking_server_keeps_accepting_after_a_client_resets() {
+    use std::os::unix::io::AsRawFd;
+
+    let addr = start(&Arc::new(VecDequeMailbox::new(
+        10,
+        EvictionPolicy::DropOldest,
+    )));
+    for _ in 0..20 {
+        let client = TcpStream::connect(addr).unwrap();
+        // closing with a zero linger time resets the connection, possibly before it is accepted
+        let linger = libc::linger {
+            l_onoff: 1,
+            l_linger: 0,
+        };
+        // Safety: the socket is open and the option value has the size passed along
+        let set = unsafe {
+            libc::setsockopt(
+                client.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_LINGER,
+                &linger as *const libc::linger as *const libc::c_void,
+                std::mem::size_of::<libc::linger>() as libc::socklen_t,
+            )
+        };
+        assert_eq!(set, 0);
+    }
+    let mut client = BufReader::new(TcpStream::connect(addr).unwrap());
+    assert_eq!(send(&mut client, "PUBLISH hello\n"), "OK 1\n");
+}
@@ -0,0 +1,48 @@
+// Every test crate compiles its own copy and uses only some of the helpers
+#![allow(dead_code)]
+
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::process::{Child, Command};
+use std::thread;
+use std::time::Duration;
+
+/// The server binary, killed when dropped
+pub struct Server(Child);
+
+impl Server {
+    /// Starts the server binary with the arguments
+    pub fn start(args: &[&str]) -> Server {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_tcp-server"));
+        command.args(args);
+        Server::spawn(command)
+    }
+
+    /// Starts a command which runs the server binary
+    pub fn spawn(mut command: Command) -> Server {
+        Server(command.spawn().unwrap())
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+pub fn free_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+pub fn connect(addr: SocketAddr) -> TcpStream {
+    for _ in 0..100 {
+        if let Ok(stream) = TcpStream::connect(addr) {
+            return stream;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    panic!("server is not listening on {}", addr);
+}
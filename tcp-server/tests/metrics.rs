@@ -0,0 +1,63 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::SocketAddr;
+use std::thread;
+use std::time::Duration;
+
+use common::{connect, free_addr, Server};
+
+mod common;
+
+fn http_get(addr: SocketAddr, path: &str) -> String {
+    let mut stream = connect(addr);
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, addr
+    );
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn metrics_endpoint_serves_prometheus_text_format() {
+    let bind = free_addr();
+    let metrics_addr = free_addr();
+    let _server = Server::start(&[
+        "--bind",
+        &bind.to_string(),
+        "--metrics-addr",
+        &metrics_addr.to_string(),
+    ]);
+
+    let mut client = connect(bind);
+    client.write_all(b"PUBLISH hello\n").unwrap();
+    let mut reply = String::new();
+    BufReader::new(&client).read_line(&mut reply).unwrap();
+    assert_eq!(reply, "OK 1\n");
+    client.write_all(b"PUBLISH broken").unwrap();
+    client.shutdown(std::net::Shutdown::Write).unwrap();
+    thread::sleep(Duration::from_millis(100));
+
+    let response = http_get(metrics_addr, "/metrics");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.contains("content-type: text/plain; version=0.0.4\r\n"));
+    for line in [
+        "redisish_connections_total 1",
+        "redisish_commands_total{verb=\"PUBLISH\"} 1",
+        "redisish_command_duration_seconds_count{verb=\"PUBLISH\"} 1",
+        "redisish_parse_errors_total{error=\"MissingNewline\"} 1",
+        "redisish_mailbox_messages{channel=\"misc\"} 1",
+        "redisish_mailbox_bytes 5",
+        "redisish_evicted_messages_total 0",
+    ] {
+        assert!(response.lines().any(|it| it == line), "missing {}", line);
+    }
+
+    let response = http_get(metrics_addr, "/other");
+    assert!(
+        response.starts_with("HTTP/1.1 404 Not Found\r\n"),
+        "{}",
+        response
+    );
+}
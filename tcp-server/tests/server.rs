@@ -0,0 +1,190 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use tcp_server::mailbox::{EvictionPolicy, SlowSubscriberPolicy, VecDequeMailbox};
+use tcp_server::{Server, ServerHandle};
+
+async fn start(mailbox: &Arc<VecDequeMailbox>) -> ServerHandle {
+    Server::builder()
+        .bind("127.0.0.1:0".parse().unwrap())
+        .mailbox(mailbox.clone())
+        .start()
+        .await
+        .unwrap()
+}
+
+async fn request(addr: SocketAddr, command: &str) -> String {
+    let mut stream = BufReader::new(TcpStream::connect(addr).await.unwrap());
+    stream
+        .get_mut()
+        .write_all(command.as_bytes())
+        .await
+        .unwrap();
+    let mut reply = String::new();
+    stream.read_line(&mut reply).await.unwrap();
+    reply
+}
+
+#[tokio::test]
+async fn servers_on_ephemeral_ports_are_isolated_unless_they_share_a_mailbox() {
+    let mailbox = Arc::new(VecDequeMailbox::new(10, EvictionPolicy::DropOldest));
+    let first = start(&mailbox).await;
+    let second = start(&mailbox).await;
+    let other = start(&Arc::new(VecDequeMailbox::new(
+        10,
+        EvictionPolicy::DropOldest,
+    )))
+    .await;
+    let (first_addr, second_addr) = (first.local_addr().unwrap(), second.local_addr().unwrap());
+    assert_ne!(first_addr.port(), 0);
+    assert_ne!(first_addr, second_addr);
+
+    assert_eq!(request(first_addr, "PUBLISH hello\n").await, "OK 1\n");
+    assert_eq!(request(second_addr, "RETRIEVE\n").await, "hello;\n");
+    assert_eq!(
+        request(other.local_addr().unwrap(), "RETRIEVE\n").await,
+        "\n"
+    );
+    assert_eq!(&*mailbox.list_emails().await, "hello;");
+}
+
+#[tokio::test]
+async fn retrieve_since_returns_only_newer_emails() {
+    let mailbox = Arc::new(VecDequeMailbox::new(10, EvictionPolicy::DropOldest));
+    let server = start(&mailbox).await;
+    let mut client = BufReader::new(
+        TcpStream::connect(server.local_addr().unwrap())
+            .await
+            .unwrap(),
+    );
+    for message in ["a", "b", "c"] {
+        send(&mut client, &format!("PUBLISH {}\n", message)).await;
+    }
+
+    assert_eq!(send(&mut client, "RETRIEVE SINCE 1\n").await, "c;b;\n");
+    assert_eq!(
+        send(&mut client, "RETRIEVE WITHMETA SINCE 2\n").await,
+        "1\n"
+    );
+    let mut line = String::new();
+    client.read_line(&mut line).await.unwrap();
+    assert!(line.starts_with("3 "), "{}", line);
+    assert!(line.ends_with(" misc c\n"), "{}", line);
+    assert_eq!(send(&mut client, "RETRIEVE SINCE 3\n").await, "\n");
+}
+
+#[tokio::test]
+#[cfg(unix)]
+async fn shutdown_disconnects_clients_and_stops_listening() {
+    let mailbox = Arc::new(VecDequeMailbox::new(10, EvictionPolicy::DropOldest));
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("redisish.sock");
+    let server = Server::builder()
+        .bind("127.0.0.1:0".parse().unwrap())
+        .mailbox(mailbox)
+        .unix_socket(&path, None)
+        .http_addr("127.0.0.1:0".parse().unwrap())
+        .start()
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    assert!(server.http_addr().is_some());
+    let mut client = BufReader::new(TcpStream::connect(addr).await.unwrap());
+    client
+        .get_mut()
+        .write_all(b"SUBSCRIBE misc\n")
+        .await
+        .unwrap();
+    let mut reply = String::new();
+    client.read_line(&mut reply).await.unwrap();
+    assert_eq!(reply, "OK\n");
+
+    server.shutdown().await;
+    reply.clear();
+    assert_eq!(client.read_line(&mut reply).await.unwrap(), 0);
+    assert!(TcpStream::connect(addr).await.is_err());
+    assert!(!path.exists());
+}
+
+async fn send(stream: &mut BufReader<TcpStream>, command: &str) -> String {
+    stream
+        .get_mut()
+        .write_all(command.as_bytes())
+        .await
+        .unwrap();
+    let mut reply = String::new();
+    stream.read_line(&mut reply).await.unwrap();
+    reply
+}
+
+#[tokio::test]
+async fn clients_are_named_listed_and_killed() {
+    let mailbox = Arc::new(VecDequeMailbox::new(10, EvictionPolicy::DropOldest));
+    let server = start(&mailbox).await;
+    let addr = server.local_addr().unwrap();
+    let mut named = BufReader::new(TcpStream::connect(addr).await.unwrap());
+    let mut operator = BufReader::new(TcpStream::connect(addr).await.unwrap());
+
+    assert_eq!(send(&mut named, "CLIENT SETNAME tui\n").await, "OK\n");
+    assert_eq!(send(&mut named, "PUBLISH hello\n").await, "OK 1\n");
+    let messages = mailbox.list_messages().await;
+    assert_eq!(messages[0].client_name.as_deref(), Some("tui"));
+
+    assert_eq!(send(&mut operator, "CLIENT LIST\n").await, "2\n");
+    let mut clients = vec![];
+    for _ in 0..2 {
+        let mut line = String::new();
+        operator.read_line(&mut line).await.unwrap();
+        clients.push(line.split(' ').map(str::to_owned).collect::<Vec<_>>());
+    }
+    // <id> <addr> <name> <age> <idle> <commands>
+    let tui = clients.iter().find(|it| it[2] == "tui").unwrap();
+    assert_eq!(tui[1], named.get_ref().local_addr().unwrap().to_string());
+    assert_eq!(tui[5], "2\n");
+    let other = clients.iter().find(|it| it[2] == "-").unwrap();
+    assert_eq!(other[5], "1\n");
+
+    let kill = format!("CLIENT KILL {}\n", tui[0]);
+    assert_eq!(send(&mut operator, &kill).await, "OK\n");
+    let mut reply = String::new();
+    assert_eq!(named.read_line(&mut reply).await.unwrap(), 0);
+    assert_eq!(
+        send(&mut operator, &kill).await,
+        format!("ERR Client error, unknown client: {}\n", tui[0])
+    );
+}
+
+#[tokio::test]
+async fn backpressure_does_not_block_a_connection_publishing_to_its_own_channel() {
+    let mailbox = Arc::new(
+        VecDequeMailbox::new(10, EvictionPolicy::DropOldest)
+            .with_subscribers(1, SlowSubscriberPolicy::Backpressure),
+    );
+    let server = start(&mailbox).await;
+    let mut client = BufReader::new(
+        TcpStream::connect(server.local_addr().unwrap())
+            .await
+            .unwrap(),
+    );
+    client
+        .get_mut()
+        .write_all(b"SUBSCRIBE misc\nPUBLISH a\nPUBLISH b\nPUBLISH c\n")
+        .await
+        .unwrap();
+
+    let mut replies = vec![];
+    while replies.len() < 4 {
+        let mut line = String::new();
+        let read = tokio::time::timeout(Duration::from_secs(2), client.read_line(&mut line));
+        read.await.unwrap().unwrap();
+        if !line.starts_with("MESSAGE ") {
+            replies.push(line);
+        }
+    }
+    assert_eq!(replies, vec!["OK\n", "OK 1\n", "OK 2\n", "OK 3\n"]);
+    assert_eq!(mailbox.disconnected_subscribers().await, 0);
+}
@@ -0,0 +1,93 @@
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+
+use rcgen::CertifiedKey;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+use common::{connect, free_addr, Server};
+
+mod common;
+
+/// Generates a self-signed certificate for `localhost` and writes it next to its key
+fn generate_certificate(dir: &Path) -> CertifiedKey {
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+    fs::write(dir.join("cert.pem"), certified.cert.pem()).unwrap();
+    fs::write(dir.join("key.pem"), certified.key_pair.serialize_pem()).unwrap();
+    certified
+}
+
+fn start_tls_server(dir: &Path) -> (Server, SocketAddr) {
+    let bind = free_addr();
+    let server = Server::start(&[
+        "--bind",
+        &bind.to_string(),
+        "--tls-cert",
+        dir.join("cert.pem").to_str().unwrap(),
+        "--tls-key",
+        dir.join("key.pem").to_str().unwrap(),
+    ]);
+    (server, bind)
+}
+
+fn tls_connect(
+    addr: SocketAddr,
+    trusted: &CertifiedKey,
+    server_name: &str,
+) -> StreamOwned<ClientConnection, std::net::TcpStream> {
+    let mut roots = RootCertStore::empty();
+    roots.add(trusted.cert.der().clone()).unwrap();
+    let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let server_name = ServerName::try_from(server_name.to_owned()).unwrap();
+    let connection = ClientConnection::new(Arc::new(config), server_name).unwrap();
+    StreamOwned::new(connection, connect(addr))
+}
+
+#[test]
+fn serves_redisish_over_tls() {
+    let dir = tempfile::tempdir().unwrap();
+    let certified = generate_certificate(dir.path());
+    let (_server, addr) = start_tls_server(dir.path());
+
+    let mut client = BufReader::new(tls_connect(addr, &certified, "localhost"));
+    client.get_mut().write_all(b"PUBLISH hello\n").unwrap();
+    let mut reply = String::new();
+    client.read_line(&mut reply).unwrap();
+    assert_eq!(reply, "OK 1\n");
+    client.get_mut().write_all(b"RETRIEVE\n").unwrap();
+    reply.clear();
+    client.read_line(&mut reply).unwrap();
+    assert_eq!(reply, "hello;\n");
+}
+
+#[test]
+fn rejects_untrusted_clients_and_plaintext() {
+    let dir = tempfile::tempdir().unwrap();
+    let certified = generate_certificate(dir.path());
+    let (_server, addr) = start_tls_server(dir.path());
+
+    // the certificate is not valid for this name
+    let mut client = tls_connect(addr, &certified, "example.com");
+    assert!(client.write_all(b"PUBLISH hello\n").is_err());
+
+    // another self-signed certificate is not trusted
+    let other = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+    let mut client = tls_connect(addr, &other, "localhost");
+    assert!(client.write_all(b"PUBLISH hello\n").is_err());
+
+    // the server closes the connection after a failed handshake instead of replying
+    let mut plaintext = connect(addr);
+    plaintext.write_all(b"PUBLISH hello\n").unwrap();
+    let mut reply = Vec::new();
+    let _ = plaintext.read_to_end(&mut reply);
+    assert!(!reply.starts_with(b"OK"));
+}
@@ -0,0 +1,68 @@
+#![cfg(unix)]
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use common::{connect, free_addr, Server};
+
+mod common;
+
+fn connect_unix(path: &Path) -> UnixStream {
+    for _ in 0..100 {
+        if let Ok(stream) = UnixStream::connect(path) {
+            return stream;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    panic!("server is not listening on {}", path.display());
+}
+
+fn request(stream: &mut impl Write, reader: &mut impl BufRead, command: &str) -> String {
+    stream.write_all(command.as_bytes()).unwrap();
+    let mut reply = String::new();
+    reader.read_line(&mut reply).unwrap();
+    reply
+}
+
+#[test]
+fn serves_the_same_mailbox_on_unix_socket_and_tcp() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("redisish.sock");
+    let bind = free_addr();
+    let _server = Server::start(&[
+        "--bind",
+        &bind.to_string(),
+        "--unix-socket",
+        path.to_str().unwrap(),
+        "--unix-socket-mode",
+        "600",
+    ]);
+
+    let mut unix = connect_unix(&path);
+    let mode = path.metadata().unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    let mut unix_reader = BufReader::new(unix.try_clone().unwrap());
+    assert_eq!(
+        request(&mut unix, &mut unix_reader, "PUBLISH from-unix\n"),
+        "OK 1\n"
+    );
+
+    let mut tcp = connect(bind);
+    let mut tcp_reader = BufReader::new(tcp.try_clone().unwrap());
+    assert_eq!(
+        request(&mut tcp, &mut tcp_reader, "RETRIEVE\n"),
+        "from-unix;\n"
+    );
+    assert_eq!(
+        request(&mut tcp, &mut tcp_reader, "RETRIEVE WITHMETA\n"),
+        "1\n"
+    );
+    let mut line = String::new();
+    tcp_reader.read_line(&mut line).unwrap();
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    assert_eq!(fields[2..], ["unix", "-", "misc", "from-unix"]);
+}